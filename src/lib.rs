@@ -0,0 +1,2638 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Problems with the structure of a program, found while parsing
+#[derive(Debug)]
+pub enum ParseError {
+    /// A `]` at the given opcode position has no `[` before it
+    UnmatchedLoopEnd(usize),
+    /// A `[` at the given opcode position is never closed
+    UnmatchedLoopBegin(usize),
+    /// Loops are nested `depth` levels deep, more than the `limit` allows
+    NestingTooDeep { depth: usize, limit: usize },
+}
+
+/// Changes `repair` made to balance the loops of a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// The `]` at the given opcode position had no `[` before it and was dropped
+    DroppedLoopEnd(usize),
+    /// The `[` at the given opcode position was never closed, so a `]` was added at the end
+    ClosedLoopBegin(usize),
+}
+
+/// Problems that stop a running program, each with the position of the operation
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The data pointer moved left of the first cell
+    PointerUnderflow(usize),
+    /// The data pointer moved right of the last cell
+    PointerOverflow(usize),
+    /// Reading the input failed
+    Input(usize, std::io::Error),
+    /// A `,` was executed after the input had ended, with `Io::strict_eof`
+    UnexpectedEof(usize),
+    /// The guarded cell, given second, was written to
+    GuardViolation(usize, usize),
+    /// Writing the output failed
+    Output(usize, std::io::Error),
+}
+
+/// Problems with the characters of a program, found while lexing strictly
+#[derive(Debug)]
+pub enum LexError {
+    /// A character that is neither an opcode nor whitespace, with its line and column
+    UnexpectedChar { character: char, line: usize, column: usize },
+}
+
+/// Problems found while expanding macros
+#[derive(Debug)]
+pub enum MacroError {
+    /// A `@name` is used without a definition
+    Undefined(String),
+    /// Expanding the named macro nested deeper than `MACRO_DEPTH_LIMIT`
+    TooDeep(String),
+    /// The expanded program grew past this many bytes
+    TooLong(usize),
+}
+
+/// Any error that stops a program from being run
+#[derive(Debug)]
+pub enum BfError {
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnmatchedLoopEnd(i) => write!(f, "Loop ending at #{} has no beginning", i),
+            ParseError::UnmatchedLoopBegin(i) => write!(f, "Loop that starts at #{} has no matching ending!", i),
+            ParseError::NestingTooDeep { depth, limit } => write!(f, "Loops are nested {} levels deep, the limit is {}", depth, limit),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::PointerUnderflow(i) => write!(f, "Data pointer moved left of the first cell at #{}", i),
+            RuntimeError::PointerOverflow(i) => write!(f, "Data pointer moved right of the last cell at #{}", i),
+            RuntimeError::Input(i, error) => write!(f, "Failed to read input at #{}: {}", i, error),
+            RuntimeError::UnexpectedEof(i) => write!(f, "Read past the end of the input at #{}", i),
+            RuntimeError::GuardViolation(i, cell) => write!(f, "Guarded cell {} was written at #{}", cell, i),
+            RuntimeError::Output(i, error) => write!(f, "Failed to write output at #{}: {}", i, error),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnexpectedChar { character, line, column } => {
+                write!(f, "Unexpected character {:?} at line {}, column {}", character, line, column)
+            },
+        }
+    }
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroError::Undefined(name) => write!(f, "Macro @{} is not defined", name),
+            MacroError::TooDeep(name) => write!(f, "Macro @{} expands deeper than {} levels", name, MACRO_DEPTH_LIMIT),
+            MacroError::TooLong(max_len) => write!(f, "Program is longer than the limit of {} bytes", max_len),
+        }
+    }
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BfError::Parse(error) => error.fmt(f),
+            BfError::Runtime(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BfError {}
+
+impl From<ParseError> for BfError {
+    fn from(error: ParseError) -> BfError {
+        BfError::Parse(error)
+    }
+}
+
+impl From<RuntimeError> for BfError {
+    fn from(error: RuntimeError) -> BfError {
+        BfError::Runtime(error)
+    }
+}
+
+impl RuntimeError {
+    /// The same error at the opcode position of the operation it happened at, looked up in the
+    /// positions `flatten_with_positions` returns
+    ///
+    /// Operations past the end of the program keep their index.
+    pub fn at_source(self, positions: &[usize]) -> RuntimeError {
+        let source = |i: usize| positions.get(i).copied().unwrap_or(i);
+
+        match self {
+            RuntimeError::PointerUnderflow(i) => RuntimeError::PointerUnderflow(source(i)),
+            RuntimeError::PointerOverflow(i) => RuntimeError::PointerOverflow(source(i)),
+            RuntimeError::Input(i, error) => RuntimeError::Input(source(i), error),
+            RuntimeError::UnexpectedEof(i) => RuntimeError::UnexpectedEof(source(i)),
+            RuntimeError::GuardViolation(i, cell) => RuntimeError::GuardViolation(source(i), cell),
+            RuntimeError::Output(i, error) => RuntimeError::Output(source(i), error),
+        }
+    }
+}
+
+/// Opcodes determined by the lexer
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum OpCode {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    LoopBegin,
+    LoopEnd,
+    SwitchTape,
+    Duplicate,
+}
+
+/// Instruction of a parsed program, which the program and every loop body hold together with the
+/// opcode position it was parsed from
+///
+/// Optimized instructions keep the position of the first opcode they replace, so errors can
+/// still point into the source.
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum Instruction {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    Loop(Vec<(usize, Instruction)>),
+    /// Sets the current cell to zero
+    Clear,
+    /// Adds the current cell times `factor` to the cell `offset` positions away
+    MultiplyAdd { offset: isize, factor: u8 },
+    /// Adds to the current cell, wrapping around
+    Add(u8),
+    /// Moves the data pointer by several cells at once
+    Move(isize),
+    /// Sets `count` cells to zero, starting `start_offset` cells away, without moving the pointer
+    ClearRange { start_offset: isize, count: usize },
+    /// Swaps to the other tape, which has its own data pointer
+    SwitchTape,
+    /// Sets the next cell to the value of the current one
+    Duplicate,
+    /// Writes these bytes, which a program always writes before reading anything
+    Emit(Vec<u8>),
+}
+
+/// How many macros may be nested inside each other before expansion gives up
+pub const MACRO_DEPTH_LIMIT: usize = 64;
+
+/// Preprocessor that expands `@name` macros into plain source code
+///
+/// Definitions are lines of the form `@name = body` at the top of the file, where names are
+/// made of letters, digits and underscores. Blank lines may appear between them; the first other
+/// line ends the definitions. Bodies may use other macros, which are expanded in turn.
+pub fn expand_macros(source: &str) -> Result<String, MacroError> {
+    expand_macros_with_limit(source, usize::MAX)
+}
+
+/// Expands macros like `expand_macros`, giving up as soon as the program grows past `max_len`
+/// bytes instead of building all of it first
+pub fn expand_macros_with_limit(source: &str, max_len: usize) -> Result<String, MacroError> {
+    let mut macros = Vec::new();
+    let mut rest = source;
+
+    while !rest.is_empty() {
+        let (line, next) = match rest.find('\n') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, ""),
+        };
+
+        let line = line.trim();
+        if !line.is_empty() {
+            let definition = line.strip_prefix('@').and_then(|line| line.split_once('='));
+            let (name, body) = match definition {
+                Some((name, body)) if is_macro_name(name.trim()) => (name.trim(), body.trim()),
+                _ => break,
+            };
+
+            macros.push((name, body));
+        }
+
+        rest = next;
+    }
+
+    let mut expanded = String::new();
+    expand(rest, &macros, 0, max_len, &mut expanded)?;
+    Ok(expanded)
+}
+
+fn is_macro_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn expand(text: &str, macros: &[(&str, &str)], depth: usize, max_len: usize, expanded: &mut String) -> Result<(), MacroError> {
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, symbol)) = chars.next() {
+        if expanded.len() > max_len {
+            return Err(MacroError::TooLong(max_len));
+        }
+
+        if symbol != '@' {
+            expanded.push(symbol);
+            continue;
+        }
+
+        let mut end = i + 1;
+        while let Some(&(j, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            end = j + c.len_utf8();
+            chars.next();
+        }
+
+        // A lone `@` is just a comment character
+        let name = &text[i + 1..end];
+        if name.is_empty() {
+            expanded.push(symbol);
+            continue;
+        }
+
+        // Later definitions replace earlier ones
+        let body = match macros.iter().rev().find(|(defined, _)| *defined == name) {
+            Some((_, body)) => body,
+            None => return Err(MacroError::Undefined(name.to_string())),
+        };
+
+        if depth == MACRO_DEPTH_LIMIT {
+            return Err(MacroError::TooDeep(name.to_string()));
+        }
+
+        expand(body, macros, depth + 1, max_len, expanded)?;
+    }
+
+    if expanded.len() > max_len {
+        return Err(MacroError::TooLong(max_len));
+    }
+
+    Ok(())
+}
+
+/// Dialect the lexer reads, the plain language by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexOptions {
+    /// Everything from this character up to the end of the line is skipped
+    pub comment_delim: Option<char>,
+    /// Everything between `(` and its matching `)` is skipped, parentheses inside nesting
+    pub paren_comments: bool,
+    /// `$` switches between two tapes
+    pub dual_tape: bool,
+    /// `^` copies the current cell into the next one
+    pub dup: bool,
+}
+
+/// Lexer turns the source code into a sequence of opcodes, in the dialect the options describe
+pub fn lex(source: String, options: LexOptions) -> Vec<OpCode> {
+    let LexOptions { comment_delim, paren_comments, dual_tape, dup } = options;
+    let mut operations = Vec::new();
+    let mut in_comment = false;
+    let mut paren_depth = 0;
+
+    for symbol in source.chars() {
+        if in_comment {
+            in_comment = symbol != '\n';
+            continue;
+        }
+
+        if paren_comments && (symbol == '(' || paren_depth > 0) {
+            match symbol {
+                '(' => paren_depth += 1,
+                ')' => paren_depth -= 1,
+                _ => (),
+            }
+            continue;
+        }
+
+        if Some(symbol) == comment_delim {
+            in_comment = true;
+            continue;
+        }
+
+        let op = match symbol {
+            '>' => Some(OpCode::IncrementPointer),
+            '<' => Some(OpCode::DecrementPointer),
+            '+' => Some(OpCode::Increment),
+            '-' => Some(OpCode::Decrement),
+            '.' => Some(OpCode::Write),
+            ',' => Some(OpCode::Read),
+            '[' => Some(OpCode::LoopBegin),
+            ']' => Some(OpCode::LoopEnd),
+            '$' if dual_tape => Some(OpCode::SwitchTape),
+            '^' if dup => Some(OpCode::Duplicate),
+            _ => None
+        };
+
+        // Non-opcode characters are simply comments
+        if let Some(op) = op {
+            operations.push(op);
+        }
+    }
+
+    operations
+}
+
+/// Lexes like `lex`, but only allows whitespace besides the opcodes, so a stray character is an
+/// error instead of a comment
+///
+/// Lines and columns are counted from one, columns in characters.
+///
+/// Comment options are ignored, as there is no room for comments.
+pub fn lex_strict(source: String, options: LexOptions) -> Result<Vec<OpCode>, LexError> {
+    for (line, text) in source.lines().enumerate() {
+        for (column, character) in text.chars().enumerate() {
+            let allowed = match character {
+                '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']' => true,
+                '$' => options.dual_tape,
+                '^' => options.dup,
+                _ => character.is_whitespace(),
+            };
+
+            if !allowed {
+                return Err(LexError::UnexpectedChar { character, line: line + 1, column: column + 1 });
+            }
+        }
+    }
+
+    Ok(lex(source, LexOptions { dual_tape: options.dual_tape, dup: options.dup, ..LexOptions::default() }))
+}
+
+/// Balances the loops of a program instead of rejecting it, by dropping every `]` that has no
+/// `[` and closing every unclosed `[` at the end
+///
+/// Returns the repaired opcodes along with the changes that were made.
+pub fn repair(opcodes: Vec<OpCode>) -> (Vec<OpCode>, Vec<Repair>) {
+    let mut repaired = Vec::new();
+    let mut repairs = Vec::new();
+    let mut open_loops = Vec::new();
+
+    for (i, op) in opcodes.into_iter().enumerate() {
+        match op {
+            OpCode::LoopBegin => open_loops.push(i),
+            OpCode::LoopEnd if open_loops.pop().is_none() => {
+                repairs.push(Repair::DroppedLoopEnd(i));
+                continue;
+            },
+            _ => (),
+        }
+
+        repaired.push(op);
+    }
+
+    // Innermost loops are closed first
+    for i in open_loops.into_iter().rev() {
+        repairs.push(Repair::ClosedLoopBegin(i));
+        repaired.push(OpCode::LoopEnd);
+    }
+
+    (repaired, repairs)
+}
+
+/// Finds every loop in the source, returned as the byte offsets of its `[` and its matching `]`,
+/// for editors to fold them
+///
+/// Loops are ordered by where they start, so every loop comes before the ones inside it.
+/// Brackets without a match are left out.
+pub fn loop_spans(source: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut open_loops = Vec::new();
+
+    for (i, symbol) in source.char_indices() {
+        match symbol {
+            '[' => open_loops.push(i),
+            ']' => {
+                if let Some(start) = open_loops.pop() {
+                    spans.push((start, i));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    spans.sort_unstable();
+    spans
+}
+
+/// Deepest nesting of loops `parse` accepts, as the optimizer and the analyses walk loops
+/// recursively and would run out of stack on deeper programs
+pub const MAX_NESTING_DEPTH: usize = 512;
+
+/// Parses a program like `parse`, but rejects it upfront when loops are nested deeper than
+/// `max_depth`
+///
+/// Limits above `MAX_NESTING_DEPTH` still stop at that depth.
+pub fn parse_with_max_depth(opcodes: Vec<OpCode>, max_depth: usize) -> Result<Vec<(usize, Instruction)>, ParseError> {
+    let mut depth: usize = 0;
+
+    for op in &opcodes {
+        match op {
+            OpCode::LoopBegin => depth += 1,
+            OpCode::LoopEnd => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+
+        if depth > max_depth {
+            return Err(ParseError::NestingTooDeep { depth, limit: max_depth });
+        }
+    }
+
+    parse(opcodes)
+}
+
+/// Parser turns the opcodes into a program, in a single pass that keeps the enclosing blocks of
+/// the loop being parsed on a stack
+///
+/// Programs with loops nested deeper than `MAX_NESTING_DEPTH` are rejected.
+pub fn parse(opcodes: Vec<OpCode>) -> Result<Vec<(usize, Instruction)>, ParseError> {
+    let mut program: Vec<(usize, Instruction)> = Vec::new();
+    let mut open_loops: Vec<(usize, Vec<(usize, Instruction)>)> = Vec::new();
+
+    for (i, op) in opcodes.into_iter().enumerate() {
+        let instr = match op {
+            OpCode::IncrementPointer => Instruction::IncrementPointer,
+            OpCode::DecrementPointer => Instruction::DecrementPointer,
+            OpCode::Increment => Instruction::Increment,
+            OpCode::Decrement => Instruction::Decrement,
+            OpCode::Write => Instruction::Write,
+            OpCode::Read => Instruction::Read,
+            OpCode::SwitchTape => Instruction::SwitchTape,
+            OpCode::Duplicate => Instruction::Duplicate,
+
+            OpCode::LoopBegin => {
+                if open_loops.len() == MAX_NESTING_DEPTH {
+                    return Err(ParseError::NestingTooDeep { depth: MAX_NESTING_DEPTH + 1, limit: MAX_NESTING_DEPTH });
+                }
+
+                open_loops.push((i, std::mem::take(&mut program)));
+                continue;
+            },
+
+            // The loop takes the position of its `[`
+            OpCode::LoopEnd => match open_loops.pop() {
+                Some((begin, enclosing)) => {
+                    let body = std::mem::replace(&mut program, enclosing);
+                    program.push((begin, Instruction::Loop(body)));
+                    continue;
+                },
+                None => return Err(ParseError::UnmatchedLoopEnd(i)),
+            },
+        };
+
+        program.push((i, instr));
+    }
+
+    // The outermost loop is the one that is reported
+    if let Some((loop_start, _)) = open_loops.first() {
+        return Err(ParseError::UnmatchedLoopBegin(*loop_start));
+    }
+
+    Ok(program)
+}
+
+/// Parses a program given as a string literal, checking at compile time that its loops are
+/// balanced and nested no deeper than `MAX_NESTING_DEPTH`
+///
+/// ```
+/// let program = bfi_rs::bf!("+[-].");
+/// assert_eq!(program.len(), 3);
+/// ```
+///
+/// Unbalanced programs don't compile:
+///
+/// ```compile_fail
+/// let program = bfi_rs::bf!("+[-");
+/// ```
+#[macro_export]
+macro_rules! bf {
+    ($source:literal) => {{
+        const _: () = assert!($crate::loops_balanced($source), "bf! program has unbalanced loops");
+        $crate::parse($crate::lex(::std::string::String::from($source), $crate::LexOptions::default())).unwrap()
+    }};
+}
+
+/// Whether every `[` of the source has a matching `]`, with loops nested no deeper than
+/// `MAX_NESTING_DEPTH`, so that `parse` accepts it
+///
+/// This can run at compile time, which `bf!` relies on.
+pub const fn loops_balanced(source: &str) -> bool {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'[' if depth == MAX_NESTING_DEPTH => return false,
+            b'[' => depth += 1,
+            b']' if depth == 0 => return false,
+            b']' => depth -= 1,
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    depth == 0
+}
+
+/// An optimization pass, which rewrites a program into an equivalent one
+pub type Pass = fn(Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)>;
+
+/// Optimization passes with their names, in the order `optimize` runs them
+pub const PASSES: &[(&str, Pass)] = &[
+    ("lower loops", lower_loops),
+    ("fold runs", fold_runs),
+    ("remove dead code", remove_dead_code),
+    ("fuse clears", fuse_clears),
+];
+
+/// Runs every optimization pass over the program
+pub fn optimize(program: Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)> {
+    PASSES.iter().fold(program, |program, (_, pass)| pass(program))
+}
+
+/// Most instructions `fold_output` runs ahead of time before it gives up on the rest
+pub const FOLD_STEP_LIMIT: usize = 1_000_000;
+
+/// Replaces the start of a program, up to the first instruction that reads input, with the
+/// output it always writes, followed by what it takes to leave the memory the way it would
+///
+/// The program is run ahead of time on a zeroed memory of `cells` cells, starting on the first
+/// of them, one top-level instruction at a time. Running stops before the first instruction
+/// that reads input, switches tapes, leaves the memory or takes more than `FOLD_STEP_LIMIT`
+/// steps in all.
+pub fn fold_output(program: Vec<(usize, Instruction)>, cells: usize) -> Vec<(usize, Instruction)> {
+    let mut state = Folding { memory: vec![0; cells], pointer: 0, output: Vec::new(), steps: 0 };
+    let mut folded = 0;
+
+    for instr in &program {
+        let mut next = state.clone();
+        if !next.run(std::slice::from_ref(instr)) {
+            break;
+        }
+
+        state = next;
+        folded += 1;
+    }
+
+    if folded == 0 {
+        return program;
+    }
+
+    // What replaces the folded instructions starts where they did
+    let position = program[0].0;
+    let mut optimized = Vec::new();
+    if !state.output.is_empty() {
+        optimized.push((position, Instruction::Emit(state.output)));
+    }
+
+    // The cells are set up from left to right, ending on the cell the pointer was left on
+    let mut pointer = 0;
+    for (i, &value) in state.memory.iter().enumerate().filter(|(_, &value)| value != 0) {
+        if i != pointer {
+            optimized.push((position, Instruction::Move(i as isize - pointer as isize)));
+        }
+        optimized.push((position, Instruction::Add(value)));
+        pointer = i;
+    }
+
+    if state.pointer != pointer {
+        optimized.push((position, Instruction::Move(state.pointer as isize - pointer as isize)));
+    }
+
+    optimized.extend(program.into_iter().skip(folded));
+    optimized
+}
+
+/// Memory and output of a program that `fold_output` runs ahead of time
+#[derive(Clone)]
+struct Folding {
+    memory: Vec<u8>,
+    pointer: usize,
+    output: Vec<u8>,
+    steps: usize,
+}
+
+impl Folding {
+    /// Runs a block of instructions, returning false as soon as one can't be run ahead of time
+    fn run(&mut self, block: &[(usize, Instruction)]) -> bool {
+        for (_, instr) in block {
+            self.steps += 1;
+            if self.steps > FOLD_STEP_LIMIT {
+                return false;
+            }
+
+            let pointer = self.pointer;
+            match instr {
+                Instruction::IncrementPointer => match self.cell(1) {
+                    Some(cell) => self.pointer = cell,
+                    None => return false,
+                },
+                Instruction::DecrementPointer => match self.cell(-1) {
+                    Some(cell) => self.pointer = cell,
+                    None => return false,
+                },
+                Instruction::Move(offset) => match self.cell(*offset) {
+                    Some(cell) => self.pointer = cell,
+                    None => return false,
+                },
+                Instruction::Increment => self.memory[pointer] = self.memory[pointer].wrapping_add(1),
+                Instruction::Decrement => self.memory[pointer] = self.memory[pointer].wrapping_sub(1),
+                Instruction::Add(amount) => self.memory[pointer] = self.memory[pointer].wrapping_add(*amount),
+                Instruction::Clear => self.memory[pointer] = 0,
+                Instruction::Write => self.output.push(self.memory[pointer]),
+                Instruction::Emit(bytes) => self.output.extend_from_slice(bytes),
+                Instruction::Loop(nested_instructions) => {
+                    while self.memory[self.pointer] != 0 {
+                        if !self.run(nested_instructions) {
+                            return false;
+                        }
+                    }
+                },
+                Instruction::MultiplyAdd { offset, factor } => match self.cell(*offset) {
+                    Some(target) => {
+                        let product = self.memory[pointer].wrapping_mul(*factor);
+                        self.memory[target] = self.memory[target].wrapping_add(product);
+                    },
+                    None => return false,
+                },
+                Instruction::ClearRange { start_offset, count } => {
+                    let first = match self.cell(*start_offset) {
+                        Some(first) => first,
+                        None => return false,
+                    };
+
+                    match self.memory.get_mut(first..first.saturating_add(*count)) {
+                        Some(range) => range.fill(0),
+                        None => return false,
+                    }
+                },
+                Instruction::Duplicate => match self.cell(1) {
+                    Some(target) => self.memory[target] = self.memory[pointer],
+                    None => return false,
+                },
+                Instruction::Read | Instruction::SwitchTape => return false,
+            }
+        }
+
+        true
+    }
+
+    /// The cell `offset` positions away from the pointer, if it is inside the memory
+    fn cell(&self, offset: isize) -> Option<usize> {
+        self.pointer.checked_add_signed(offset).filter(|&cell| cell < self.memory.len())
+    }
+}
+
+/// Counts the instructions in a program, including those inside loops
+pub fn instruction_count(program: &[(usize, Instruction)]) -> usize {
+    program.iter().map(|(_, instr)| match instr {
+        Instruction::Loop(nested_instructions) => 1 + instruction_count(nested_instructions),
+        _ => 1,
+    }).sum()
+}
+
+/// Replaces loops that only move the current cell into other cells with direct arithmetic
+fn lower_loops(program: Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)> {
+    program.into_iter().map(|(position, instr)| match instr {
+        Instruction::Loop(nested_instructions) => match lower_copy_loop(position, &nested_instructions) {
+            Some(lowered) => (position, lowered),
+            None => (position, Instruction::Loop(lower_loops(nested_instructions))),
+        },
+        instr => (position, instr),
+    }).collect()
+}
+
+/// Lowers loops like `[->+>++<<]`, which decrement the current cell once per iteration, only
+/// increment other cells and return the pointer to where it started
+///
+/// The additions stay inside a loop that ends by clearing the current cell, so they share the
+/// original guard and run at most once. They all take the position of the loop they replace.
+fn lower_copy_loop(position: usize, body: &[(usize, Instruction)]) -> Option<Instruction> {
+    let mut offset: isize = 0;
+    let mut origin_change = 0;
+    let mut factors: Vec<(isize, u8)> = Vec::new();
+
+    for (_, instr) in body {
+        match instr {
+            Instruction::IncrementPointer => offset += 1,
+            Instruction::DecrementPointer => offset -= 1,
+            Instruction::Increment if offset == 0 => origin_change += 1,
+            Instruction::Decrement if offset == 0 => origin_change -= 1,
+            Instruction::Increment => {
+                match factors.iter_mut().find(|(target, _)| *target == offset) {
+                    Some((_, factor)) => *factor = factor.wrapping_add(1),
+                    None => factors.push((offset, 1)),
+                }
+            },
+            _ => return None,
+        }
+    }
+
+    if offset != 0 || origin_change != -1 {
+        return None;
+    }
+
+    if factors.is_empty() {
+        return Some(Instruction::Clear);
+    }
+
+    let mut lowered: Vec<(usize, Instruction)> = factors.into_iter()
+        .map(|(offset, factor)| (position, Instruction::MultiplyAdd { offset, factor }))
+        .collect();
+    lowered.push((position, Instruction::Clear));
+
+    Some(Instruction::Loop(lowered))
+}
+
+/// Serializes a program to JSON, with every instruction named after its variant and loops as
+/// `{"Loop": [...]}` objects
+pub fn to_json(program: &[(usize, Instruction)]) -> String {
+    let instructions: Vec<String> = program.iter().map(|(_, instr)| match instr {
+        Instruction::IncrementPointer => "\"IncrementPointer\"".to_string(),
+        Instruction::DecrementPointer => "\"DecrementPointer\"".to_string(),
+        Instruction::Increment => "\"Increment\"".to_string(),
+        Instruction::Decrement => "\"Decrement\"".to_string(),
+        Instruction::Write => "\"Write\"".to_string(),
+        Instruction::Read => "\"Read\"".to_string(),
+        Instruction::SwitchTape => "\"SwitchTape\"".to_string(),
+        Instruction::Duplicate => "\"Duplicate\"".to_string(),
+        Instruction::Loop(nested_instructions) => format!("{{\"Loop\":{}}}", to_json(nested_instructions)),
+        Instruction::Clear => "\"Clear\"".to_string(),
+        Instruction::MultiplyAdd { offset, factor } => {
+            format!("{{\"MultiplyAdd\":{{\"offset\":{},\"factor\":{}}}}}", offset, factor)
+        },
+        Instruction::Add(amount) => format!("{{\"Add\":{}}}", amount),
+        Instruction::Move(offset) => format!("{{\"Move\":{}}}", offset),
+        Instruction::ClearRange { start_offset, count } => {
+            format!("{{\"ClearRange\":{{\"start_offset\":{},\"count\":{}}}}}", start_offset, count)
+        },
+        Instruction::Emit(bytes) => format!("{{\"Emit\":{:?}}}", bytes),
+    }).collect();
+
+    format!("[{}]", instructions.join(","))
+}
+
+/// Folds runs of `+` and `-` into a single `Add`, and runs of `>` and `<` into a single `Move`
+fn fold_runs(program: Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)> {
+    let mut folded: Vec<(usize, Instruction)> = Vec::new();
+
+    for (position, instr) in program {
+        let instr = match instr {
+            Instruction::Increment => Instruction::Add(1),
+            Instruction::Decrement => Instruction::Add(u8::MAX),
+            Instruction::IncrementPointer => Instruction::Move(1),
+            Instruction::DecrementPointer => Instruction::Move(-1),
+            Instruction::Loop(nested_instructions) => Instruction::Loop(fold_runs(nested_instructions)),
+            instr => instr,
+        };
+
+        match (folded.last_mut(), &instr) {
+            (Some((_, Instruction::Add(total))), Instruction::Add(amount)) => *total = total.wrapping_add(*amount),
+            (Some((_, Instruction::Move(total))), Instruction::Move(offset)) => *total += offset,
+            _ => {
+                folded.push((position, instr));
+                continue;
+            },
+        }
+
+        // Runs like `+-` cancel out completely
+        if let Some((_, Instruction::Add(0) | Instruction::Move(0))) = folded.last() {
+            folded.pop();
+        }
+    }
+
+    folded
+}
+
+/// Drops clears of a cell that is already known to be zero, and loops that can never be
+/// entered because their guard is such a cell
+///
+/// Only the cell right after a clear or a loop, which both leave the current cell at zero, is
+/// known to be zero, until anything moves the pointer or changes the cell.
+fn remove_dead_code(program: Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)> {
+    let mut optimized = Vec::new();
+    let mut known_zero = false;
+
+    for (position, instr) in program {
+        match instr {
+            Instruction::Clear | Instruction::Loop(_) if known_zero => continue,
+            Instruction::Clear | Instruction::Loop(_) => known_zero = true,
+            Instruction::Write => (),
+            _ => known_zero = false,
+        }
+
+        optimized.push((position, match instr {
+            Instruction::Loop(nested_instructions) => Instruction::Loop(remove_dead_code(nested_instructions)),
+            instr => instr,
+        }));
+    }
+
+    optimized
+}
+
+/// Fuses runs like `[-]>[-]>[-]`, which clear neighbouring cells one after another, into a single
+/// `ClearRange` followed by the pointer move
+fn fuse_clears(program: Vec<(usize, Instruction)>) -> Vec<(usize, Instruction)> {
+    let mut fused: Vec<(usize, Instruction)> = Vec::new();
+
+    for (position, instr) in program {
+        let instr = match instr {
+            Instruction::Loop(nested_instructions) => Instruction::Loop(fuse_clears(nested_instructions)),
+            instr => instr,
+        };
+
+        let len = fused.len();
+        match (&mut fused[len.saturating_sub(2)..], &instr) {
+            // A clear right after the last cleared cell extends the range
+            ([(_, Instruction::Clear), (_, Instruction::Move(1))], Instruction::Clear) => {
+                fused[len - 2].1 = Instruction::ClearRange { start_offset: 0, count: 2 };
+            },
+            ([(_, Instruction::ClearRange { start_offset: 0, count }), (_, Instruction::Move(offset))], Instruction::Clear) if *offset == *count as isize => {
+                *count += 1;
+            },
+            ([.., (_, Instruction::Move(total))], Instruction::Move(offset)) => {
+                *total += offset;
+
+                if *total == 0 {
+                    fused.pop();
+                }
+            },
+            _ => fused.push((position, instr)),
+        }
+    }
+
+    fused
+}
+
+/// Finds cells that are read, by `.` or as a loop guard, before anything was written to them
+///
+/// Offsets are relative to the starting cell and positions are those of the opcodes. Only the
+/// first read of every cell is reported, and the analysis stops where the pointer position can
+/// no longer be known, after a loop that moves it.
+pub fn unwritten_reads(program: &[(usize, Instruction)]) -> Vec<(usize, isize)> {
+    let mut reads = Vec::new();
+    let mut written = HashSet::new();
+
+    lint_block(program, 0, &mut written, &mut reads);
+    reads
+}
+
+/// Lints a block starting at the given offset, returns the offset it ends at if that is known
+fn lint_block(program: &[(usize, Instruction)], mut offset: isize, written: &mut HashSet<isize>, reads: &mut Vec<(usize, isize)>) -> Option<isize> {
+    for (position, instr) in program {
+        // Marking a reported cell as written keeps it from being reported again
+        if let Instruction::Write | Instruction::Loop(_) | Instruction::Duplicate = instr {
+            if written.insert(offset) {
+                reads.push((*position, offset));
+            }
+        }
+
+        match instr {
+            Instruction::IncrementPointer => offset += 1,
+            Instruction::DecrementPointer => offset -= 1,
+            Instruction::Move(distance) => offset += distance,
+            Instruction::Increment | Instruction::Decrement | Instruction::Add(_) | Instruction::Read | Instruction::Clear => {
+                written.insert(offset);
+            },
+            Instruction::ClearRange { start_offset, count } => {
+                written.extend((0..*count as isize).map(|i| offset + start_offset + i));
+            },
+            Instruction::MultiplyAdd { offset: target, .. } => {
+                written.insert(offset + target);
+            },
+            Instruction::Write | Instruction::Emit(_) => (),
+            Instruction::Duplicate => {
+                written.insert(offset + 1);
+            },
+            // Offsets on the other tape are unrelated to these
+            Instruction::SwitchTape => return None,
+            Instruction::Loop(nested_instructions) => {
+                // The pointer is only known after a loop that ends where it started
+                if lint_block(nested_instructions, offset, written, reads) != Some(offset) {
+                    return None;
+                }
+            },
+        }
+    }
+
+    Some(offset)
+}
+
+/// Finds the loops at the top level of a program that can never be entered, because every cell
+/// starts at zero and nothing has changed their guard yet, returned as the opcode positions of
+/// their `[`
+///
+/// The analysis stops where the pointer position can no longer be known, after a loop that
+/// moves it.
+pub fn dead_loops(program: &[(usize, Instruction)]) -> Vec<usize> {
+    let mut dead = Vec::new();
+    let mut nonzero = HashSet::new();
+    let mut offset = 0;
+
+    for entry in program {
+        let (position, instr) = entry;
+        match instr {
+            Instruction::IncrementPointer => offset += 1,
+            Instruction::DecrementPointer => offset -= 1,
+            Instruction::Move(distance) => offset += distance,
+            Instruction::Clear => {
+                nonzero.remove(&offset);
+            },
+            Instruction::ClearRange { start_offset, count } => {
+                for i in 0..*count as isize {
+                    nonzero.remove(&(offset + start_offset + i));
+                }
+            },
+            Instruction::Write | Instruction::Emit(_) => (),
+            Instruction::SwitchTape => break,
+            Instruction::Loop(_) if !nonzero.contains(&offset) => dead.push(*position),
+            Instruction::Loop(nested_instructions) => {
+                // The loop can change any cell it touches, and only ends once its guard is zero
+                if touch_block(nested_instructions, offset, &mut nonzero) != Some(offset) {
+                    break;
+                }
+
+                nonzero.remove(&offset);
+            },
+            _ => {
+                touch_block(std::slice::from_ref(entry), offset, &mut nonzero);
+            },
+        }
+    }
+
+    dead
+}
+
+/// Adds every cell that a block may change to `touched`, returns the offset it ends at if that
+/// is known
+fn touch_block(program: &[(usize, Instruction)], mut offset: isize, touched: &mut HashSet<isize>) -> Option<isize> {
+    for (_, instr) in program {
+        match instr {
+            Instruction::IncrementPointer => offset += 1,
+            Instruction::DecrementPointer => offset -= 1,
+            Instruction::Move(distance) => offset += distance,
+            Instruction::MultiplyAdd { offset: target, .. } => {
+                touched.insert(offset + target);
+            },
+            Instruction::Write | Instruction::Emit(_) | Instruction::ClearRange { .. } => (),
+            Instruction::Duplicate => {
+                touched.insert(offset + 1);
+            },
+            Instruction::SwitchTape => return None,
+            Instruction::Loop(nested_instructions) => {
+                if touch_block(nested_instructions, offset, touched)? != offset {
+                    return None;
+                }
+            },
+            _ => {
+                touched.insert(offset);
+            },
+        }
+    }
+
+    Some(offset)
+}
+
+/// Works out how many cells a program can reach at most, counting from the cell it starts on,
+/// without running it
+///
+/// Returns `None` when a loop moves the pointer by a different amount than it brings it back,
+/// like `[>]`, or switches tapes, because the pointer can end up anywhere then.
+pub fn max_tape_bound(program: &[(usize, Instruction)]) -> Option<usize> {
+    let mut highest = 0;
+    bound_block(program, 0, &mut highest)?;
+
+    Some(highest as usize + 1)
+}
+
+/// Follows the pointer through a block, returns the offset it ends at
+fn bound_block(program: &[(usize, Instruction)], mut offset: isize, highest: &mut isize) -> Option<isize> {
+    for (_, instr) in program {
+        let reached = match instr {
+            Instruction::IncrementPointer => {
+                offset += 1;
+                offset
+            },
+            Instruction::DecrementPointer => {
+                offset -= 1;
+                offset
+            },
+            Instruction::Move(distance) => {
+                offset += distance;
+                offset
+            },
+            Instruction::MultiplyAdd { offset: target, .. } => offset + target,
+            Instruction::Duplicate => offset + 1,
+            Instruction::ClearRange { start_offset, count } => offset + start_offset + *count as isize - 1,
+            Instruction::SwitchTape => return None,
+            Instruction::Loop(nested_instructions) => {
+                if bound_block(nested_instructions, offset, highest)? != offset {
+                    return None;
+                }
+
+                offset
+            },
+            _ => offset,
+        };
+
+        *highest = (*highest).max(reached);
+    }
+
+    Some(offset)
+}
+
+/// Instructions laid out one after another for execution, with loops turned into jumps
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum Op {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    /// Jumps past the matching `LoopEnd` when the current cell is zero
+    LoopBegin(usize),
+    /// Jumps back behind the matching `LoopBegin` when the current cell is nonzero
+    LoopEnd(usize),
+    Clear,
+    MultiplyAdd { offset: isize, factor: u8 },
+    Add(u8),
+    Move(isize),
+    ClearRange { start_offset: isize, count: usize },
+    SwitchTape,
+    Duplicate,
+    Emit(Box<[u8]>),
+}
+
+/// Turns a parsed program into a flat sequence of operations
+pub fn flatten(instructions: &[(usize, Instruction)]) -> Vec<Op> {
+    flatten_with_positions(instructions).0
+}
+
+/// Turns a parsed program into a flat sequence of operations, along with the opcode position
+/// every operation comes from
+///
+/// Both ends of a loop take the position of its `[`. The loops being flattened are kept on a
+/// stack rather than recursed into, so the nesting depth is only limited by memory.
+pub fn flatten_with_positions(instructions: &[(usize, Instruction)]) -> (Vec<Op>, Vec<usize>) {
+    let mut ops = Vec::new();
+    let mut positions = Vec::new();
+    // Rest of every enclosing block, with where the loop it continues after begins
+    let mut open_loops: Vec<(std::slice::Iter<'_, (usize, Instruction)>, usize)> = Vec::new();
+    let mut block = instructions.iter();
+
+    loop {
+        let (position, instr) = match block.next() {
+            Some(entry) => entry,
+            None => match open_loops.pop() {
+                Some((enclosing, begin)) => {
+                    let end = ops.len();
+                    ops[begin] = Op::LoopBegin(end);
+                    ops.push(Op::LoopEnd(begin));
+                    positions.push(positions[begin]);
+                    block = enclosing;
+                    continue;
+                },
+                None => break,
+            },
+        };
+
+        let op = match instr {
+            Instruction::IncrementPointer => Op::IncrementPointer,
+            Instruction::DecrementPointer => Op::DecrementPointer,
+            Instruction::Increment => Op::Increment,
+            Instruction::Decrement => Op::Decrement,
+            Instruction::Write => Op::Write,
+            Instruction::Read => Op::Read,
+            Instruction::SwitchTape => Op::SwitchTape,
+            Instruction::Duplicate => Op::Duplicate,
+            Instruction::Emit(bytes) => Op::Emit(bytes.clone().into_boxed_slice()),
+            Instruction::Loop(nested_instructions) => {
+                open_loops.push((std::mem::replace(&mut block, nested_instructions.iter()), ops.len()));
+                Op::LoopBegin(0)
+            },
+            Instruction::Clear => Op::Clear,
+            Instruction::MultiplyAdd { offset, factor } => Op::MultiplyAdd { offset: *offset, factor: *factor },
+            Instruction::Add(amount) => Op::Add(*amount),
+            Instruction::Move(offset) => Op::Move(*offset),
+            Instruction::ClearRange { start_offset, count } => {
+                Op::ClearRange { start_offset: *start_offset, count: *count }
+            },
+        };
+
+        ops.push(op);
+        positions.push(*position);
+    }
+
+    (ops, positions)
+}
+
+/// Whether the program has a `,` anywhere, and so needs any input at all
+pub fn reads_input(ops: &[Op]) -> bool {
+    ops.iter().any(|op| matches!(op, Op::Read))
+}
+
+/// Finds the loops that can never end once they are entered, because their body only moves the
+/// pointer and always brings it back to the guard, returned as the positions of their
+/// `LoopBegin` and `LoopEnd`
+pub fn endless_loops(ops: &[Op]) -> Vec<(usize, usize)> {
+    ops.iter().enumerate().filter_map(|(begin, op)| {
+        let end = match op {
+            Op::LoopBegin(end) => *end,
+            _ => return None,
+        };
+
+        let mut offset = 0;
+        for op in &ops[begin + 1..end] {
+            match op {
+                Op::IncrementPointer => offset += 1,
+                Op::DecrementPointer => offset -= 1,
+                Op::Move(distance) => offset += distance,
+                _ => return None,
+            }
+        }
+
+        (offset == 0).then_some((begin, end))
+    }).collect()
+}
+
+/// Finds the cell `offset` positions away from the data pointer, wrapping around inside the band
+/// when the data pointer is in it
+fn offset_cell(data_pointer: usize, offset: isize, band: Option<(usize, usize)>, memory_size: usize, program_counter: usize) -> Result<usize, RuntimeError> {
+    let target = match band {
+        Some((low, high)) if (low..=high).contains(&data_pointer) => {
+            let width = (high - low + 1) as isize;
+            let position = (data_pointer - low) as isize;
+            Some(low + (position + offset).rem_euclid(width) as usize)
+        },
+        _ => data_pointer.checked_add_signed(offset),
+    };
+
+    match target {
+        Some(target) if target < memory_size => Ok(target),
+        Some(_) => Err(RuntimeError::PointerOverflow(program_counter)),
+        None => Err(RuntimeError::PointerUnderflow(program_counter)),
+    }
+}
+
+/// Where a running program reads its input from and writes its output to
+pub struct Io<'a> {
+    pub input: Box<dyn Read + 'a>,
+    pub output: Box<dyn Write + 'a>,
+    /// Copy every byte that is read to the output, like a terminal would
+    pub echo_input: bool,
+    /// Written to the output and flushed before every read, so the user sees what is asked for
+    pub prompt: Vec<u8>,
+    /// Make a read past the end of the input an error, instead of leaving the cell unchanged
+    pub strict_eof: bool,
+}
+
+impl<'a> Io<'a> {
+    /// Reads from `input` and writes to `output`, without echo or prompt, leaving the cell
+    /// unchanged at a read past the end of the input
+    pub fn new(input: impl Read + 'a, output: impl Write + 'a) -> Io<'a> {
+        Io {
+            input: Box::new(input),
+            output: Box::new(output),
+            echo_input: false,
+            prompt: Vec::new(),
+            strict_eof: false,
+        }
+    }
+
+    pub fn echo_input(mut self, echo_input: bool) -> Io<'a> {
+        self.echo_input = echo_input;
+        self
+    }
+
+    pub fn prompt(mut self, prompt: impl Into<Vec<u8>>) -> Io<'a> {
+        self.prompt = prompt.into();
+        self
+    }
+
+    pub fn strict_eof(mut self, strict_eof: bool) -> Io<'a> {
+        self.strict_eof = strict_eof;
+        self
+    }
+}
+
+/// Storage for the cells of a tape, so that embedders can bring their own, like a sparse one
+/// for programs that use cells far apart
+///
+/// Cells are numbered from zero up to `len`, and `run_steps` never goes past that.
+pub trait Tape {
+    /// A tape of `len` cells that are all zero
+    fn zeroed(len: usize) -> Self where Self: Sized;
+
+    /// Number of cells
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, index: usize) -> u8;
+
+    fn set(&mut self, index: usize, value: u8);
+
+    /// Sets the cells from `first` up to and including `last` to zero
+    fn clear_cells(&mut self, first: usize, last: usize) {
+        for index in first..=last {
+            self.set(index, 0);
+        }
+    }
+}
+
+impl Tape for Vec<u8> {
+    fn zeroed(len: usize) -> Vec<u8> {
+        vec![0; len]
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    #[inline]
+    fn get(&self, index: usize) -> u8 {
+        self[index]
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, value: u8) {
+        self[index] = value;
+    }
+
+    fn clear_cells(&mut self, first: usize, last: usize) {
+        self[first..=last].fill(0);
+    }
+}
+
+/// Tape that only stores the cells that aren't zero, so programs can use cells far apart
+/// without the memory in between being allocated
+pub struct SparseTape {
+    cells: BTreeMap<usize, u8>,
+    len: usize,
+}
+
+impl SparseTape {
+    pub fn new(len: usize) -> SparseTape {
+        SparseTape { cells: BTreeMap::new(), len }
+    }
+}
+
+impl Tape for SparseTape {
+    fn zeroed(len: usize) -> SparseTape {
+        SparseTape::new(len)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.cells.get(&index).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        if value == 0 {
+            self.cells.remove(&index);
+        } else {
+            self.cells.insert(index, value);
+        }
+    }
+
+    fn clear_cells(&mut self, first: usize, last: usize) {
+        let cleared: Vec<usize> = self.cells.range(first..=last).map(|(index, _)| *index).collect();
+
+        for index in cleared {
+            self.cells.remove(&index);
+        }
+    }
+}
+
+/// Execution state of a program, which can be advanced one operation at a time
+///
+/// The cells are kept in a `Vec<u8>` unless the machine is made with `with_tape`.
+pub struct Machine<T: Tape = Vec<u8>> {
+    pub bf_memory: T,
+    pub data_pointer: usize,
+    /// Index of the next operation to execute
+    pub program_counter: usize,
+    /// Highest data pointer reached so far
+    pub peak_pointer: usize,
+    /// `run_steps` returns early as soon as the data pointer moves past this cell
+    pub pointer_limit: usize,
+    /// Number of operations executed so far
+    pub steps: u64,
+    /// First and last cell of a band the data pointer wraps around in, instead of stopping at
+    /// the ends of the memory
+    pub band: Option<(usize, usize)>,
+    /// The tape that `SwitchTape` swaps `bf_memory` with, empty until it is first used
+    pub other_tape: T,
+    /// Data pointer of the other tape
+    pub other_pointer: usize,
+    /// Whether `bf_memory` currently holds the second tape
+    pub on_second_tape: bool,
+    /// Cell that `run_steps` stops with an error for as soon as an operation writes to it, even
+    /// when the value stays the same
+    pub guard_cell: Option<usize>,
+    /// Cell that is numbered zero when cells are shown, the cells left of it get negative numbers
+    pub origin: usize,
+    /// Whether the last operations ran on the loop without input handling
+    without_input: bool,
+}
+
+impl Machine {
+    pub fn new(memory_size: usize) -> Machine {
+        Machine::with_tape(vec![0; memory_size])
+    }
+}
+
+impl<T: Tape> Machine<T> {
+    /// Makes a machine that keeps its cells on the given tape
+    pub fn with_tape(tape: T) -> Machine<T> {
+        Machine {
+            bf_memory: tape,
+            data_pointer: 0,
+            program_counter: 0,
+            peak_pointer: 0,
+            pointer_limit: usize::MAX,
+            steps: 0,
+            band: None,
+            other_tape: T::zeroed(0),
+            other_pointer: 0,
+            on_second_tape: false,
+            guard_cell: None,
+            origin: 0,
+            without_input: false,
+        }
+    }
+
+    /// Executes at most `steps` operations, returns false once the program has finished
+    ///
+    /// Returns early, after fewer operations, when the data pointer moves past `pointer_limit`.
+    pub fn run_steps(&mut self, ops: &[Op], steps: u64, io: &mut Io<'_>) -> Result<bool, RuntimeError> {
+        // Checking the guard after every operation is slow, so it gets its own copy of the loop
+        if self.guard_cell.is_some() {
+            self.run_steps_with::<true, true>(ops, steps, io)
+        } else {
+            self.run_steps_with::<false, true>(ops, steps, io)
+        }
+    }
+
+    /// Executes operations like `run_steps`, for programs that `reads_input` says never read,
+    /// in a copy of the loop without any input handling
+    ///
+    /// The input of `io` is never touched, a `,` keeps the cell as if the input had ended.
+    pub fn run_steps_no_input(&mut self, ops: &[Op], steps: u64, io: &mut Io<'_>) -> Result<bool, RuntimeError> {
+        if self.guard_cell.is_some() {
+            self.run_steps_with::<true, false>(ops, steps, io)
+        } else {
+            self.run_steps_with::<false, false>(ops, steps, io)
+        }
+    }
+
+    fn run_steps_with<const GUARDED: bool, const READS: bool>(&mut self, ops: &[Op], steps: u64, io: &mut Io<'_>) -> Result<bool, RuntimeError> {
+        if self.data_pointer >= self.bf_memory.len() {
+            return Err(RuntimeError::PointerOverflow(self.program_counter));
+        }
+
+        // Work on local copies of the registers, the compiler can't keep fields in registers
+        // across the calls to read and write
+        let bf_memory = &mut self.bf_memory;
+        let mut data_pointer = self.data_pointer;
+        let mut program_counter = self.program_counter;
+        let mut peak_pointer = self.peak_pointer;
+        let mut steps = steps;
+        let mut executed = 0;
+        let mut result = Ok(());
+
+        // Cells where moving the pointer any further either wraps around or fails
+        let last_cell = bf_memory.len() - 1;
+        let band = self.band.map(|(low, high)| (low.min(last_cell), high.min(last_cell)));
+        let (lower_edge, upper_edge) = band.unwrap_or((0, last_cell));
+
+        // Operations that write the guarded cell set this, whatever value they write
+        let guard = self.guard_cell.filter(|cell| *cell <= last_cell);
+        let mut guard_written = false;
+
+        macro_rules! wrote {
+            ($cell:expr) => {
+                if GUARDED && guard == Some($cell) {
+                    guard_written = true;
+                }
+            };
+        }
+
+        // Records the pointer reaching a cell, and stops after this operation once that is
+        // past the pointer limit
+        macro_rules! reach {
+            ($cell:expr) => {
+                if $cell > peak_pointer {
+                    peak_pointer = $cell;
+
+                    if peak_pointer > self.pointer_limit {
+                        steps = executed + 1;
+                    }
+                }
+            };
+        }
+
+        // The data pointer always stays inside the memory, so indexing it can't fail
+        while executed < steps {
+            let op = match ops.get(program_counter) {
+                Some(op) => op,
+                None => break,
+            };
+
+            match op {
+                Op::IncrementPointer => {
+                    if data_pointer >= upper_edge {
+                        if band.is_none() {
+                            result = Err(RuntimeError::PointerOverflow(program_counter));
+                            break;
+                        }
+
+                        data_pointer = lower_edge;
+                    } else {
+                        data_pointer += 1;
+                    }
+
+                    reach!(data_pointer);
+                },
+                Op::DecrementPointer => {
+                    if data_pointer <= lower_edge {
+                        if band.is_none() {
+                            result = Err(RuntimeError::PointerUnderflow(program_counter));
+                            break;
+                        }
+
+                        data_pointer = upper_edge;
+                    } else {
+                        data_pointer -= 1;
+                    }
+
+                    reach!(data_pointer);
+                },
+                Op::Increment => {
+                    bf_memory.set(data_pointer, bf_memory.get(data_pointer).wrapping_add(1));
+                    wrote!(data_pointer);
+                },
+                Op::Decrement => {
+                    bf_memory.set(data_pointer, bf_memory.get(data_pointer).wrapping_sub(1));
+                    wrote!(data_pointer);
+                },
+                Op::Write => {
+                    if let Err(error) = io.output.write_all(&[bf_memory.get(data_pointer)]) {
+                        result = Err(RuntimeError::Output(program_counter, error));
+                        break;
+                    }
+                },
+                Op::Emit(bytes) => {
+                    if let Err(error) = io.output.write_all(bytes) {
+                        result = Err(RuntimeError::Output(program_counter, error));
+                        break;
+                    }
+                },
+                Op::Read if !READS => (),
+                Op::Read => {
+                    let mut byte: [u8; 1] = [0; 1];
+
+                    if !io.prompt.is_empty() {
+                        if let Err(error) = io.output.write_all(&io.prompt).and_then(|_| io.output.flush()) {
+                            result = Err(RuntimeError::Output(program_counter, error));
+                            break;
+                        }
+                    }
+
+                    match io.input.read_exact(&mut byte) {
+                        Ok(()) => {
+                            bf_memory.set(data_pointer, byte[0]);
+                            wrote!(data_pointer);
+
+                            if io.echo_input {
+                                if let Err(error) = io.output.write_all(&byte) {
+                                    result = Err(RuntimeError::Output(program_counter, error));
+                                    break;
+                                }
+                            }
+                        },
+                        // Past the end of the input, the cell keeps its value
+                        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof && !io.strict_eof => (),
+                        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                            result = Err(RuntimeError::UnexpectedEof(program_counter));
+                            break;
+                        },
+                        Err(error) => {
+                            result = Err(RuntimeError::Input(program_counter, error));
+                            break;
+                        },
+                    }
+                },
+                Op::LoopBegin(end) => {
+                    if bf_memory.get(data_pointer) == 0 {
+                        program_counter = *end;
+                    }
+                },
+                Op::LoopEnd(begin) => {
+                    if bf_memory.get(data_pointer) != 0 {
+                        program_counter = *begin;
+                    }
+                },
+                Op::Clear => {
+                    bf_memory.set(data_pointer, 0);
+                    wrote!(data_pointer);
+                },
+                Op::MultiplyAdd { offset, factor } => {
+                    let target = match offset_cell(data_pointer, *offset, band, bf_memory.len(), program_counter) {
+                        Ok(target) => target,
+                        Err(error) => {
+                            result = Err(error);
+                            break;
+                        },
+                    };
+
+                    let product = bf_memory.get(data_pointer).wrapping_mul(*factor);
+                    bf_memory.set(target, bf_memory.get(target).wrapping_add(product));
+                    wrote!(target);
+
+                    // The pointer would have visited the target in the original loop
+                    reach!(target);
+                },
+                Op::Add(amount) => {
+                    bf_memory.set(data_pointer, bf_memory.get(data_pointer).wrapping_add(*amount));
+                    wrote!(data_pointer);
+                },
+                Op::Move(offset) => {
+                    let target = match offset_cell(data_pointer, *offset, band, bf_memory.len(), program_counter) {
+                        Ok(target) => target,
+                        Err(error) => {
+                            result = Err(error);
+                            break;
+                        },
+                    };
+
+                    // Wrapping around the band passes its last cell on the way
+                    let wrapped = (*offset > 0) != (target > data_pointer);
+                    reach!(if wrapped { upper_edge } else { target });
+
+                    data_pointer = target;
+                },
+                Op::ClearRange { start_offset, count } => {
+                    // A range at least as wide as the band covers all of it, while its ends would
+                    // wrap around to cells in between
+                    let in_band = band.is_some_and(|(low, high)| (low..=high).contains(&data_pointer));
+
+                    let (first, last) = if in_band && *count > upper_edge - lower_edge {
+                        (lower_edge, upper_edge)
+                    } else {
+                        let first = offset_cell(data_pointer, *start_offset, band, bf_memory.len(), program_counter);
+                        let last = offset_cell(data_pointer, start_offset + *count as isize - 1, band, bf_memory.len(), program_counter);
+
+                        match (first, last) {
+                            (Ok(first), Ok(last)) => (first, last),
+                            (Err(error), _) | (_, Err(error)) => {
+                                result = Err(error);
+                                break;
+                            },
+                        }
+                    };
+
+                    // A range that wraps around the band is cleared in two parts
+                    if first <= last {
+                        bf_memory.clear_cells(first, last);
+                    } else {
+                        bf_memory.clear_cells(first, upper_edge);
+                        bf_memory.clear_cells(lower_edge, last);
+                    }
+
+                    if let (true, Some(cell)) = (GUARDED, guard) {
+                        let cleared = if first <= last { (first..=last).contains(&cell) } else { cell >= first || cell <= last };
+                        guard_written |= cleared;
+                    }
+                },
+                Op::Duplicate => {
+                    let target = match offset_cell(data_pointer, 1, band, bf_memory.len(), program_counter) {
+                        Ok(target) => target,
+                        Err(error) => {
+                            result = Err(error);
+                            break;
+                        },
+                    };
+
+                    bf_memory.set(target, bf_memory.get(data_pointer));
+                    wrote!(target);
+                    reach!(target);
+                },
+                Op::SwitchTape => {
+                    if self.other_tape.is_empty() {
+                        self.other_tape = T::zeroed(bf_memory.len());
+                    }
+
+                    std::mem::swap(bf_memory, &mut self.other_tape);
+                    std::mem::swap(&mut data_pointer, &mut self.other_pointer);
+                    self.on_second_tape = !self.on_second_tape;
+                },
+            }
+
+            // The guard watches the cell on whichever tape is in use
+            if let (true, true, Some(cell)) = (GUARDED, guard_written, guard) {
+                result = Err(RuntimeError::GuardViolation(program_counter, cell));
+                break;
+            }
+
+            program_counter += 1;
+            executed += 1;
+        }
+
+        self.data_pointer = data_pointer;
+        self.program_counter = program_counter;
+        self.peak_pointer = peak_pointer;
+        self.steps += executed;
+        self.without_input = !READS;
+
+        result.map(|_| program_counter < ops.len())
+    }
+
+    /// Runs operations like `run_steps`, or like `run_steps_no_input` when `reads` is false,
+    /// which is what `reads_input` says about them
+    pub fn run_steps_reading(&mut self, ops: &[Op], steps: u64, io: &mut Io<'_>, reads: bool) -> Result<bool, RuntimeError> {
+        if reads {
+            self.run_steps(ops, steps, io)
+        } else {
+            self.run_steps_no_input(ops, steps, io)
+        }
+    }
+
+    /// Whether the last operations that ran went through the loop without input handling
+    pub fn ran_without_input(&self) -> bool {
+        self.without_input
+    }
+
+    /// Puts the machine back into the state of a new one, keeping the memory it has allocated
+    pub fn reset(&mut self) {
+        if !self.bf_memory.is_empty() {
+            let last_cell = self.bf_memory.len() - 1;
+            self.bf_memory.clear_cells(0, last_cell);
+        }
+
+        self.data_pointer = 0;
+        self.program_counter = 0;
+        self.peak_pointer = 0;
+        self.pointer_limit = usize::MAX;
+        self.steps = 0;
+        self.band = None;
+        self.other_tape = T::zeroed(0);
+        self.other_pointer = 0;
+        self.on_second_tape = false;
+        self.guard_cell = None;
+        self.without_input = false;
+    }
+
+    /// Finds the cell `offset` positions away from the data pointer, the way `Move` and
+    /// `MultiplyAdd` do
+    pub fn cell_at_offset(&self, offset: isize) -> Option<usize> {
+        offset_cell(self.data_pointer, offset, self.band, self.bf_memory.len(), self.program_counter).ok()
+    }
+
+    /// Position of the data pointer on the tape in use
+    pub fn pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// The number a cell is shown with, counting from `origin`
+    pub fn cell_number(&self, index: usize) -> isize {
+        index as isize - self.origin as isize
+    }
+
+    /// Index in the memory of the cell with the given number, if it lies inside the memory
+    pub fn cell_index(&self, number: isize) -> Option<usize> {
+        self.origin.checked_add_signed(number).filter(|&index| index < self.bf_memory.len())
+    }
+}
+
+impl Machine {
+    /// Writes the machine state to a checkpoint file
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let bf_memory: Vec<String> = self.bf_memory.iter().map(|cell| cell.to_string()).collect();
+        let mut checkpoint = format!("{}\n{}\n{}\n", self.data_pointer, self.program_counter, bf_memory.join(" "));
+
+        // The other tape only follows once the program has used it
+        if !self.other_tape.is_empty() {
+            let other_tape: Vec<String> = self.other_tape.iter().map(|cell| cell.to_string()).collect();
+            checkpoint += &format!("{}\n{}\n{}\n", u8::from(self.on_second_tape), self.other_pointer, other_tape.join(" "));
+        }
+
+        // Write to a temporary file first so a crash never leaves a half-written checkpoint behind
+        let temp_path = format!("{}.tmp", path);
+        std::fs::write(&temp_path, checkpoint)?;
+        std::fs::rename(&temp_path, path)
+    }
+
+    /// Restores a machine state previously written by `save`
+    pub fn load(path: &str) -> std::io::Result<Machine> {
+        let checkpoint = std::fs::read_to_string(path)?;
+        let mut lines = checkpoint.lines();
+
+        let invalid = |what| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid {} in checkpoint", what));
+
+        let data_pointer = lines.next().and_then(|line| line.parse().ok()).ok_or_else(|| invalid("data pointer"))?;
+        let program_counter = lines.next().and_then(|line| line.parse().ok()).ok_or_else(|| invalid("program counter"))?;
+        let bf_memory = lines.next().ok_or_else(|| invalid("memory"))?
+            .split_whitespace()
+            .map(|cell| cell.parse())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("memory cell"))?;
+
+        let on_second_tape = match lines.next() {
+            Some(line) => line.parse::<u8>().map_err(|_| invalid("active tape"))? == 1,
+            None => false,
+        };
+
+        let other_pointer = match lines.next() {
+            Some(line) => line.parse().map_err(|_| invalid("data pointer"))?,
+            None => 0,
+        };
+
+        let other_tape = match lines.next() {
+            Some(line) => line.split_whitespace()
+                .map(|cell| cell.parse())
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| invalid("memory cell"))?,
+            None => Vec::new(),
+        };
+
+        Ok(Machine {
+            bf_memory,
+            data_pointer,
+            program_counter,
+            peak_pointer: data_pointer,
+            pointer_limit: usize::MAX,
+            steps: 0,
+            band: None,
+            other_tape,
+            other_pointer,
+            on_second_tape,
+            guard_cell: None,
+            origin: 0,
+            without_input: false,
+        })
+    }
+
+    /// The tape in use, borrowed so it can be inspected between `run_steps` calls without copying
+    pub fn tape(&self) -> &[u8] {
+        &self.bf_memory
+    }
+
+    /// The first and the second tape, whichever is in use, the second one being empty until the
+    /// program first switches to it
+    pub fn tapes(&self) -> (&[u8], &[u8]) {
+        if self.on_second_tape {
+            (&self.other_tape, &self.bf_memory)
+        } else {
+            (&self.bf_memory, &self.other_tape)
+        }
+    }
+
+    /// Renders the cells up to `window` positions either side of the data pointer,
+    /// with the current cell in brackets
+    pub fn render_window(&self, window: usize) -> String {
+        let first = self.data_pointer.saturating_sub(window);
+        let last = self.data_pointer.saturating_add(window);
+
+        let cells: Vec<String> = self.bf_memory.iter().enumerate()
+            .skip(first)
+            .take_while(|(i, _)| *i <= last)
+            .map(|(i, cell)| {
+                if i == self.data_pointer {
+                    format!("[{}:{}]", self.cell_number(i), cell)
+                } else {
+                    format!("{}:{}", self.cell_number(i), cell)
+                }
+            })
+            .collect();
+
+        cells.join(" ")
+    }
+}
+
+/// Writer that spells out bytes a terminal can't show as `\xNN`, keeping printable ASCII,
+/// newlines and tabs as they are
+struct SanitizedWriter<W: Write>(W);
+
+impl<W: Write> Write for SanitizedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            match byte {
+                b' '..=b'~' | b'\n' | b'\t' => self.0.write_all(&[*byte])?,
+                _ => write!(self.0, "\\x{:02x}", byte)?,
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Wraps output that goes to a terminal so bytes it can't show are written as `\xNN` escapes,
+/// and leaves any other output raw
+pub fn sanitize_for_terminal<'a>(output: impl Write + 'a, is_terminal: bool) -> Box<dyn Write + 'a> {
+    if is_terminal {
+        Box::new(SanitizedWriter(output))
+    } else {
+        Box::new(output)
+    }
+}
+
+/// A terminal whose settings can be read and changed, the way `stty` does it
+#[cfg(feature = "raw-tty")]
+pub trait Terminal {
+    /// The current settings, in a form `apply` takes back
+    fn settings(&mut self) -> Option<String>;
+
+    /// Changes the settings, returning false when that failed
+    fn apply(&mut self, settings: &[&str]) -> bool;
+}
+
+/// Settings for cbreak mode, where every keypress is read right away and not echoed
+///
+/// Ctrl-C doesn't send a signal in this mode, it is read as `INTERRUPT` like any other key so
+/// that `CbreakInput` can put the terminal back before the process ends.
+#[cfg(feature = "raw-tty")]
+pub const CBREAK: [&str; 5] = ["-icanon", "-echo", "-isig", "min", "1"];
+
+/// The byte Ctrl-C is read as in cbreak mode
+#[cfg(feature = "raw-tty")]
+pub const INTERRUPT: u8 = 0x03;
+
+#[cfg(feature = "raw-tty")]
+impl<T: Terminal + ?Sized> Terminal for &mut T {
+    fn settings(&mut self) -> Option<String> {
+        (**self).settings()
+    }
+
+    fn apply(&mut self, settings: &[&str]) -> bool {
+        (**self).apply(settings)
+    }
+}
+
+/// Puts a terminal into cbreak mode, returning the settings to give `restore_terminal` to put
+/// it back
+#[cfg(feature = "raw-tty")]
+pub fn enter_cbreak(terminal: &mut impl Terminal) -> Result<String, &'static str> {
+    let saved = terminal.settings().ok_or("Failed to read the terminal settings")?;
+
+    if !terminal.apply(&CBREAK) {
+        terminal.apply(&[&saved]);
+        return Err("Failed to put the terminal into cbreak mode");
+    }
+
+    Ok(saved)
+}
+
+/// Puts back the settings `enter_cbreak` saved
+#[cfg(feature = "raw-tty")]
+pub fn restore_terminal(terminal: &mut impl Terminal, saved: &str) {
+    terminal.apply(&[saved]);
+}
+
+/// Input read from a terminal in cbreak mode, which puts the terminal back and calls
+/// `on_interrupt` once Ctrl-C is pressed
+///
+/// When `on_interrupt` returns, the read fails, and so does every read after it.
+#[cfg(feature = "raw-tty")]
+pub struct CbreakInput<R: Read, T: Terminal, F: FnMut()> {
+    input: R,
+    terminal: T,
+    saved: String,
+    on_interrupt: F,
+    interrupted: bool,
+}
+
+#[cfg(feature = "raw-tty")]
+impl<R: Read, T: Terminal, F: FnMut()> CbreakInput<R, T, F> {
+    /// Reads from `input`, with the settings `enter_cbreak` saved for `terminal`
+    pub fn new(input: R, terminal: T, saved: String, on_interrupt: F) -> CbreakInput<R, T, F> {
+        CbreakInput { input, terminal, saved, on_interrupt, interrupted: false }
+    }
+}
+
+#[cfg(feature = "raw-tty")]
+impl<R: Read, T: Terminal, F: FnMut()> Read for CbreakInput<R, T, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = if self.interrupted { 0 } else { self.input.read(buf)? };
+
+        // The keys typed after Ctrl-C are dropped along with it
+        if self.interrupted || buf[..length].contains(&INTERRUPT) {
+            if !self.interrupted {
+                self.interrupted = true;
+                restore_terminal(&mut self.terminal, &self.saved);
+                (self.on_interrupt)();
+            }
+
+            return Err(std::io::Error::other("Interrupted"));
+        }
+
+        Ok(length)
+    }
+}
+
+/// Writer that hands every byte to a closure as soon as it is written
+struct CallbackWriter<F: FnMut(u8)>(F);
+
+impl<F: FnMut(u8)> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            (self.0)(*byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Input that yields a predefined sequence of bytes, for driving programs that read without a
+/// real standard input
+///
+/// Once the bytes run out, reading either starts over from the first one or reports the end of
+/// the input.
+pub struct ScriptedInput {
+    bytes: Vec<u8>,
+    position: usize,
+    looping: bool,
+}
+
+impl ScriptedInput {
+    /// Input that ends after the given bytes
+    pub fn new(bytes: impl Into<Vec<u8>>) -> ScriptedInput {
+        ScriptedInput { bytes: bytes.into(), position: 0, looping: false }
+    }
+
+    /// Input that repeats the given bytes forever, or ends right away if there are none
+    pub fn looping(bytes: impl Into<Vec<u8>>) -> ScriptedInput {
+        ScriptedInput { bytes: bytes.into(), position: 0, looping: true }
+    }
+}
+
+impl Read for ScriptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.looping && self.position == self.bytes.len() {
+            self.position = 0;
+        }
+
+        let remaining = &self.bytes[self.position..];
+        let length = remaining.len().min(buf.len());
+        buf[..length].copy_from_slice(&remaining[..length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Runs operations on the machine until the program finishes
+fn run_ops(machine: &mut Machine, ops: &[Op], input: &[u8], output: &mut dyn Write) -> Result<(), RuntimeError> {
+    let mut io = Io::new(input, output);
+
+    // Programs that never read don't need the input handling
+    let reads = reads_input(ops);
+    while machine.run_steps_reading(ops, u64::MAX, &mut io, reads)? {}
+
+    Ok(())
+}
+
+/// Runs a program on the given input, calling `on_output` for every byte the program writes
+/// as soon as it is written
+pub fn run_with_callback(source: &str, input: &[u8], on_output: impl FnMut(u8)) -> Result<(), BfError> {
+    let program = parse(lex(source.to_string(), LexOptions::default()))?;
+    run_ops(&mut Machine::new(1024), &flatten(&program), input, &mut CallbackWriter(on_output))?;
+
+    Ok(())
+}
+
+/// Runs a program on the given input and returns everything it wrote
+pub fn run_bytes(source: &[u8], input: &[u8]) -> Result<Vec<u8>, BfError> {
+    let program = parse(lex(String::from_utf8_lossy(source).into_owned(), LexOptions::default()))?;
+
+    let mut output = Vec::new();
+    run_ops(&mut Machine::new(1024), &flatten(&program), input, &mut output)?;
+
+    Ok(output)
+}
+
+/// Runs a program on the given input and returns what it wrote as text, with invalid UTF-8
+/// replaced
+pub fn run_str(source: &str, input: &str) -> Result<String, BfError> {
+    let output = run_bytes(source.as_bytes(), input.as_bytes())?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Parses and optimizes a program once, returning a function that runs it on a fresh memory
+/// for every input and returns everything it wrote
+pub fn compile_to_fn(source: &str) -> Result<impl FnMut(&[u8]) -> Result<Vec<u8>, BfError>, BfError> {
+    let program = optimize(parse(lex(source.to_string(), LexOptions::default()))?);
+    let (ops, positions) = flatten_with_positions(&program);
+
+    Ok(move |input: &[u8]| {
+        let mut output = Vec::new();
+        run_ops(&mut Machine::new(1024), &ops, input, &mut output).map_err(|error| error.at_source(&positions))?;
+
+        Ok(output)
+    })
+}
+
+/// Why a run with `Limits` stopped, when it didn't stop with an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The program ran to its end
+    Completed,
+    /// The program used up the steps it was allowed
+    StepLimit,
+    /// The program ran for longer than it was allowed
+    Timeout,
+    /// The data pointer moved past the pointer limit
+    Halted,
+}
+
+/// Limits for `Runner::run_with_limits`, where `None` allows any amount
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    /// Last cell the data pointer may move to
+    pub pointer_limit: Option<usize>,
+}
+
+/// How many steps a run with a timeout takes between looking at the clock
+const TIMEOUT_CHECK_STEPS: u64 = 100_000;
+
+/// Runs programs one after another on the same memory, which is cleared between runs instead
+/// of being allocated again
+pub struct Runner {
+    machine: Machine,
+}
+
+impl Runner {
+    pub fn new(memory_size: usize) -> Runner {
+        Runner { machine: Machine::new(memory_size) }
+    }
+
+    /// Runs a program on the given input and returns everything it wrote
+    pub fn run(&mut self, source: &str, input: &[u8]) -> Result<Vec<u8>, BfError> {
+        let program = parse(lex(source.to_string(), LexOptions::default()))?;
+        self.machine.reset();
+
+        let mut output = Vec::new();
+        run_ops(&mut self.machine, &flatten(&program), input, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Runs a program like `run`, but stops it once it reaches one of the limits, returning
+    /// which one along with everything it wrote until then
+    pub fn run_with_limits(&mut self, source: &str, input: &[u8], limits: Limits) -> Result<(RunOutcome, Vec<u8>), BfError> {
+        let ops = flatten(&parse(lex(source.to_string(), LexOptions::default()))?);
+        let reads = reads_input(&ops);
+        self.machine.reset();
+        self.machine.pointer_limit = limits.pointer_limit.unwrap_or(usize::MAX);
+
+        let mut output = Vec::new();
+        let mut io = Io::new(input, &mut output);
+        let started = Instant::now();
+
+        let outcome = loop {
+            let mut steps = if limits.timeout.is_some() { TIMEOUT_CHECK_STEPS } else { u64::MAX };
+
+            if let Some(max_steps) = limits.max_steps {
+                if self.machine.steps >= max_steps {
+                    break RunOutcome::StepLimit;
+                }
+
+                steps = steps.min(max_steps - self.machine.steps);
+            }
+
+            if !self.machine.run_steps_reading(&ops, steps, &mut io, reads)? {
+                break RunOutcome::Completed;
+            }
+
+            if self.machine.peak_pointer > self.machine.pointer_limit {
+                break RunOutcome::Halted;
+            }
+
+            if limits.timeout.is_some_and(|timeout| started.elapsed() > timeout) {
+                break RunOutcome::Timeout;
+            }
+        };
+
+        drop(io);
+        Ok((outcome, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a program in the default dialect
+    fn program(source: &str) -> Vec<(usize, Instruction)> {
+        parse(lex(source.to_string(), LexOptions::default())).unwrap()
+    }
+
+    const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    #[test]
+    fn unmatched_brackets_are_errors() {
+        assert!(matches!(run_str("+]", ""), Err(BfError::Parse(ParseError::UnmatchedLoopEnd(1)))));
+        assert!(matches!(run_str("[+", ""), Err(BfError::Parse(ParseError::UnmatchedLoopBegin(0)))));
+    }
+
+    #[test]
+    fn pointer_underflow_is_an_error() {
+        assert!(matches!(run_str("<", ""), Err(BfError::Runtime(RuntimeError::PointerUnderflow(0)))));
+    }
+
+    #[test]
+    fn moving_past_the_memory_is_an_error() {
+        let source = ">".repeat(1024);
+        assert!(matches!(run_str(&source, ""), Err(BfError::Runtime(RuntimeError::PointerOverflow(1023)))));
+        assert!(matches!(run_bytes(b"+[>+]", b""), Err(BfError::Runtime(RuntimeError::PointerOverflow(_)))));
+    }
+
+    #[test]
+    fn deeply_nested_loops_are_an_error() {
+        let depth = 200_000;
+        let source = format!("+{}-{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(matches!(run_str(&source, ""), Err(BfError::Parse(ParseError::NestingTooDeep { .. }))));
+        assert!(matches!(run_bytes(source.as_bytes(), b""), Err(BfError::Parse(ParseError::NestingTooDeep { .. }))));
+    }
+
+    #[test]
+    fn loops_nested_up_to_the_limit_run() {
+        let source = format!("+{}-{}", "[".repeat(MAX_NESTING_DEPTH), "]".repeat(MAX_NESTING_DEPTH));
+        let program = program(&source);
+
+        // The innermost loop becomes a clear, and the outer ones are kept
+        assert_eq!(flatten(&optimize(program)).len(), 2 * (MAX_NESTING_DEPTH - 1) + 2);
+        assert_eq!(run_str(&source, "").unwrap(), "");
+    }
+
+    #[test]
+    fn line_comments_may_hold_brackets() {
+        let options = LexOptions { comment_delim: Some(';'), ..LexOptions::default() };
+        let program = parse(lex("+ ; use ] freely\n.".to_string(), options)).unwrap();
+
+        assert!(matches!(program[..], [(0, Instruction::Increment), (1, Instruction::Write)]));
+    }
+
+    #[test]
+    fn window_shows_cells_around_the_pointer() {
+        let mut machine = Machine::new(16);
+        machine.data_pointer = 3;
+        machine.bf_memory[1] = 7;
+
+        assert_eq!(machine.render_window(2), "1:7 2:0 [3:0] 4:0 5:0");
+    }
+
+    #[test]
+    fn balanced_loops_are_told_apart() {
+        assert!(loops_balanced("+[->[+]<]."));
+        assert!(!loops_balanced("+[-"));
+        assert!(!loops_balanced("]["));
+        assert!(!loops_balanced(&"[".repeat(MAX_NESTING_DEPTH + 1)));
+    }
+
+    #[test]
+    fn bf_macro_parses_the_program() {
+        let program = bf!("+[-].");
+        assert!(matches!(&program[..], [(0, Instruction::Increment), (1, Instruction::Loop(body)), (4, Instruction::Write)] if matches!(body[..], [(2, Instruction::Decrement)])));
+    }
+
+    #[test]
+    fn duplicate_loop_fills_both_neighbours() {
+        let program = optimize(program("[->+>+<<]"));
+        let body = match &program[..] {
+            [(_, Instruction::Loop(body))] => body,
+            _ => panic!("{:?} is not a single loop", program),
+        };
+        assert!(matches!(body[..], [
+            (_, Instruction::MultiplyAdd { offset: 1, factor: 1 }),
+            (_, Instruction::MultiplyAdd { offset: 2, factor: 1 }),
+            (_, Instruction::Clear),
+        ]));
+
+        let mut machine = Machine::new(8);
+        machine.bf_memory[0] = 5;
+        let mut io = Io::new(std::io::empty(), std::io::sink());
+        machine.run_steps(&flatten(&program), u64::MAX, &mut io).unwrap();
+
+        assert_eq!(machine.bf_memory[..3], [0, 5, 5]);
+    }
+
+    #[test]
+    fn clear_of_a_cleared_cell_is_removed() {
+        let source = "+[-][-].";
+        let program = optimize(program(source));
+
+        assert!(matches!(program[..], [(_, Instruction::Add(1)), (_, Instruction::Clear), (_, Instruction::Write)]));
+        assert_eq!(run_str(source, "").unwrap(), "\0");
+    }
+
+    #[test]
+    fn callback_gets_every_byte_in_order() {
+        let mut bytes = Vec::new();
+        run_with_callback(HELLO_WORLD, b"", |byte| bytes.push(byte)).unwrap();
+
+        assert_eq!(bytes, b"Hello World!\n");
+    }
+
+    #[test]
+    fn compiled_program_runs_again_with_new_input() {
+        let mut echo = compile_to_fn(",.,.,.").unwrap();
+
+        assert_eq!(echo(b"abc").unwrap(), b"abc");
+        assert_eq!(echo(b"xyz").unwrap(), b"xyz");
+    }
+
+    #[test]
+    fn loop_after_a_clear_is_removed() {
+        let cleared = optimize(program("[-][+]"));
+        assert!(matches!(cleared[..], [(_, Instruction::Clear)]));
+
+        // Once the pointer moves, the next cell may be anything
+        let moved = optimize(program("[-]>[+]"));
+        assert!(matches!(moved[..], [(_, Instruction::Clear), (_, Instruction::Move(1)), (_, Instruction::Loop(_))]));
+    }
+
+    #[test]
+    fn loops_are_json_objects() {
+        let program = program("+[-]");
+        assert_eq!(to_json(&program), r#"["Increment",{"Loop":["Decrement"]}]"#);
+    }
+
+    #[test]
+    fn macros_expand_before_running() {
+        let source = expand_macros("@two = ++\n@four = @two@two\n@four@four.").unwrap();
+        assert_eq!(run_str(&source, "").unwrap(), "\u{8}");
+    }
+
+    #[test]
+    fn recursive_macros_hit_the_depth_limit() {
+        assert!(matches!(expand_macros("@loop = +@loop\n@loop"), Err(MacroError::TooDeep(name)) if name == "loop"));
+        assert!(matches!(expand_macros("@missing"), Err(MacroError::Undefined(_))));
+    }
+
+    #[test]
+    fn neighbouring_clears_fuse_into_a_range() {
+        let source = "+>+>+<<[-]>[-]>[-]<<.>.>.";
+        let program = optimize(program(source));
+
+        assert!(program.iter().any(|(_, instr)| matches!(instr, Instruction::ClearRange { start_offset: 0, count: 3 })));
+        assert_eq!(compile_to_fn(source).unwrap()(b"").unwrap(), b"\0\0\0");
+    }
+
+    #[test]
+    fn repair_balances_the_loops() {
+        let (opcodes, repairs) = repair(lex("+[+".to_string(), LexOptions::default()));
+        assert_eq!(repairs, [Repair::ClosedLoopBegin(1)]);
+        assert!(parse(opcodes).is_ok());
+
+        let (opcodes, repairs) = repair(lex("]+".to_string(), LexOptions::default()));
+        assert_eq!(repairs, [Repair::DroppedLoopEnd(0)]);
+        assert!(matches!(parse(opcodes).unwrap()[..], [(_, Instruction::Increment)]));
+    }
+
+    #[test]
+    fn reading_an_untouched_cell_is_reported() {
+        let program = program(">.<+.");
+        assert_eq!(unwritten_reads(&program), [(1, 1)]);
+    }
+
+    #[test]
+    fn reads_past_the_end_keep_the_cell() {
+        assert_eq!(run_str("+,.", "").unwrap(), "\u{1}");
+
+        let mut output = Vec::new();
+        let mut io = Io::new(&b""[..], &mut output).strict_eof(true);
+        let result = Machine::new(8).run_steps(&flatten(&program("+,.")), u64::MAX, &mut io);
+
+        assert!(matches!(result, Err(RuntimeError::UnexpectedEof(1))));
+    }
+
+    #[test]
+    fn optimized_errors_point_into_the_source() {
+        assert!(matches!(compile_to_fn("+[-]<").unwrap()(b""), Err(BfError::Runtime(RuntimeError::PointerUnderflow(4)))));
+
+        let program = optimize(program("+[->+<]<"));
+        let (ops, positions) = flatten_with_positions(&program);
+        assert_eq!(positions.len(), ops.len());
+        assert_eq!(positions.last(), Some(&7));
+    }
+
+    #[test]
+    fn paren_comments_may_hold_brackets() {
+        let options = LexOptions { paren_comments: true, ..LexOptions::default() };
+        let program = parse(lex("+( loop ] not (counted] ) either [ ).".to_string(), options)).unwrap();
+
+        assert!(matches!(program[..], [(_, Instruction::Increment), (_, Instruction::Write)]));
+        assert!(matches!(parse(lex("+( loop ] ).".to_string(), LexOptions::default())), Err(ParseError::UnmatchedLoopEnd(1))));
+    }
+
+    #[test]
+    fn writes_to_the_guarded_cell_are_stopped() {
+        let run_guarded = |source: &str, input: &'static [u8], memory: [u8; 2]| {
+            let mut machine = Machine::new(8);
+            machine.bf_memory[..2].copy_from_slice(&memory);
+            machine.guard_cell = Some(0);
+            let ops = flatten(&optimize(program(source)));
+            let mut io = Io::new(input, std::io::sink());
+            machine.run_steps(&ops, u64::MAX, &mut io)
+        };
+
+        assert!(matches!(run_guarded(">+<+", b"", [0, 0]), Err(RuntimeError::GuardViolation(3, 0))));
+        // Writes that leave the value as it was count too
+        assert!(matches!(run_guarded(",", b"a", [b'a', 0]), Err(RuntimeError::GuardViolation(0, 0))));
+        assert!(matches!(run_guarded("[-]", b"", [0, 0]), Err(RuntimeError::GuardViolation(0, 0))));
+        assert!(matches!(run_guarded(">[-<+>]", b"", [0, 0]), Ok(false)));
+        assert!(matches!(run_guarded(">.<.", b"", [1, 1]), Ok(false)));
+    }
+
+    #[test]
+    fn runner_starts_every_program_on_a_clean_tape() {
+        let mut runner = Runner::new(64);
+        assert_eq!(runner.run("+++++>++>,.", b"a").unwrap(), b"a");
+        let memory = runner.machine.bf_memory.as_ptr();
+
+        assert_eq!(runner.run(".>.>.", b"").unwrap(), [0, 0, 0]);
+        assert_eq!(runner.machine.bf_memory.as_ptr(), memory);
+    }
+
+    #[test]
+    fn scripted_input_drives_reads() {
+        let ops = flatten(&program(",.,.,."));
+        let echo = |input: ScriptedInput, strict_eof: bool| {
+            let mut output = Vec::new();
+            let mut io = Io::new(input, &mut output).strict_eof(strict_eof);
+            let result = Machine::new(8).run_steps(&ops, u64::MAX, &mut io);
+            drop(io);
+            (result, output)
+        };
+
+        assert!(matches!(echo(ScriptedInput::new("abc"), false), (Ok(false), output) if output == b"abc"));
+        assert!(matches!(echo(ScriptedInput::looping("ab"), false), (Ok(false), output) if output == b"aba"));
+        assert!(matches!(echo(ScriptedInput::new("ab"), true), (Err(RuntimeError::UnexpectedEof(4)), output) if output == b"ab"));
+    }
+
+    #[test]
+    fn no_input_loop_leaves_the_input_alone() {
+        let ops = flatten(&program("+.,."));
+        let mut output = Vec::new();
+        let mut io = Io::new(&b"a"[..], &mut output).strict_eof(true);
+
+        assert!(matches!(Machine::new(8).run_steps_no_input(&ops, u64::MAX, &mut io), Ok(false)));
+        drop(io);
+        assert_eq!(output, [1, 1]);
+    }
+
+    #[test]
+    fn tape_bound_follows_the_pointer() {
+        let bound = |source: &str| max_tape_bound(&program(source));
+
+        assert_eq!(bound(">>>"), Some(4));
+        assert_eq!(bound(">>>[-<+>]<<"), Some(4));
+        assert_eq!(bound("[>]"), None);
+    }
+
+    #[test]
+    fn programs_run_on_a_custom_tape() {
+        /// Tape that counts how often its cells are written
+        struct CountingTape {
+            cells: Vec<u8>,
+            writes: usize,
+        }
+
+        impl Tape for CountingTape {
+            fn zeroed(len: usize) -> CountingTape {
+                CountingTape { cells: vec![0; len], writes: 0 }
+            }
+
+            fn len(&self) -> usize {
+                self.cells.len()
+            }
+
+            fn get(&self, index: usize) -> u8 {
+                self.cells[index]
+            }
+
+            fn set(&mut self, index: usize, value: u8) {
+                self.cells[index] = value;
+                self.writes += 1;
+            }
+        }
+
+        let ops = flatten(&program("++>+++[-<+>]<."));
+        let mut machine = Machine::with_tape(CountingTape::zeroed(4));
+        let mut output = Vec::new();
+        let mut io = Io::new(std::io::empty(), &mut output);
+        machine.run_steps(&ops, u64::MAX, &mut io).unwrap();
+        drop(io);
+
+        assert_eq!(output, [5]);
+        assert_eq!(machine.bf_memory.writes, 11);
+    }
+
+    #[test]
+    fn only_loops_with_a_zero_guard_are_dead() {
+        let program = program("[+]+[-]>[<]");
+        assert_eq!(dead_loops(&program), [0, 8]);
+    }
+
+    #[test]
+    fn only_terminal_output_is_sanitized() {
+        let mut shown = Vec::new();
+        sanitize_for_terminal(&mut shown, true).write_all(&[b'a', 200, b'\n']).unwrap();
+        assert_eq!(shown, b"a\\xc8\n");
+
+        let mut piped = Vec::new();
+        sanitize_for_terminal(&mut piped, false).write_all(&[b'a', 200, b'\n']).unwrap();
+        assert_eq!(piped, [b'a', 200, b'\n']);
+    }
+
+    #[test]
+    #[ignore = "measures wall-clock time, run on an idle machine with --ignored --release"]
+    fn parse_time_grows_linearly() {
+        // The fastest of a few runs, to keep the noise of a busy machine down
+        let parse_time = |loops: usize| {
+            let source = expand_macros(&format!("@copy = [->+<[->+<]]\n{}", "@copy".repeat(loops))).unwrap();
+            (0..3).map(|_| {
+                let opcodes = lex(source.clone(), LexOptions::default());
+                let started = Instant::now();
+                parse(opcodes).unwrap();
+                started.elapsed()
+            }).min().unwrap()
+        };
+
+        let small = parse_time(10_000);
+        let large = parse_time(80_000);
+
+        // Eight times the loops, where a quadratic parser would take 64 times as long
+        assert!(large < small * 24, "{:?} for 8 times the loops of {:?}", large, small);
+    }
+
+    #[test]
+    fn duplicate_copies_into_the_next_cell() {
+        let options = LexOptions { dup: true, ..LexOptions::default() };
+        let ops = flatten(&parse(lex("+++^".to_string(), options)).unwrap());
+        let mut machine = Machine::new(8);
+        let mut io = Io::new(std::io::empty(), std::io::sink());
+        machine.run_steps(&ops, u64::MAX, &mut io).unwrap();
+        assert_eq!(machine.bf_memory[..3], [3, 3, 0]);
+
+        // Without the extension, `^` is a comment
+        assert_eq!(flatten(&program("+++^")).len(), 3);
+    }
+
+    #[test]
+    fn tape_is_visible_between_steps() {
+        let ops = flatten(&program(">+"));
+        let mut machine = Machine::new(4);
+        let mut io = Io::new(std::io::empty(), std::io::sink());
+
+        assert!(machine.run_steps(&ops, 1, &mut io).unwrap());
+        assert_eq!((machine.pointer(), machine.tape()), (1, &[0, 0, 0, 0][..]));
+
+        assert!(!machine.run_steps(&ops, 1, &mut io).unwrap());
+        assert_eq!(machine.tape(), [0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn output_before_any_read_is_folded() {
+        let program = fold_output(optimize(program(HELLO_WORLD)), 1024);
+
+        assert!(matches!(&program[0], (0, Instruction::Emit(bytes)) if bytes == b"Hello World!\n"));
+        assert!(program[1..].iter().all(|(_, instr)| matches!(instr, Instruction::Add(_) | Instruction::Move(_))));
+        assert_eq!(compile_to_fn(HELLO_WORLD).unwrap()(b"").unwrap(), b"Hello World!\n");
+    }
+
+    /// Terminal that keeps its settings as a string and remembers every change
+    #[cfg(feature = "raw-tty")]
+    struct FakeTerminal {
+        settings: String,
+        applied: Vec<String>,
+    }
+
+    #[cfg(feature = "raw-tty")]
+    impl Terminal for FakeTerminal {
+        fn settings(&mut self) -> Option<String> {
+            Some(self.settings.clone())
+        }
+
+        fn apply(&mut self, settings: &[&str]) -> bool {
+            self.settings = settings.join(" ");
+            self.applied.push(self.settings.clone());
+            true
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "raw-tty")]
+    fn cbreak_mode_is_entered_and_restored() {
+        let mut terminal = FakeTerminal { settings: "cooked".to_string(), applied: Vec::new() };
+
+        let saved = enter_cbreak(&mut terminal).unwrap();
+        assert_eq!(terminal.settings, "-icanon -echo -isig min 1");
+
+        restore_terminal(&mut terminal, &saved);
+        assert_eq!(terminal.settings, "cooked");
+        assert_eq!(terminal.applied, ["-icanon -echo -isig min 1", "cooked"]);
+    }
+
+    #[test]
+    #[cfg(feature = "raw-tty")]
+    fn ctrl_c_restores_the_terminal() {
+        let mut terminal = FakeTerminal { settings: "cooked".to_string(), applied: Vec::new() };
+        let saved = enter_cbreak(&mut terminal).unwrap();
+
+        let mut interrupts = 0;
+        let mut input = CbreakInput::new(&b"a\x03b"[..], &mut terminal, saved, || interrupts += 1);
+        let mut byte = [0];
+
+        input.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, *b"a");
+        assert!(input.read_exact(&mut byte).is_err());
+        assert!(input.read_exact(&mut byte).is_err());
+
+        drop(input);
+        assert_eq!(interrupts, 1);
+        assert_eq!(terminal.settings, "cooked");
+    }
+
+    #[test]
+    fn loop_spans_cover_nested_loops() {
+        assert_eq!(loop_spans("[[]]"), [(0, 3), (1, 2)]);
+        assert_eq!(loop_spans("+[-]>[<]]"), [(1, 3), (5, 7)]);
+    }
+
+    #[test]
+    fn step_limit_keeps_the_output_so_far() {
+        let mut runner = Runner::new(64);
+        let limits = Limits { max_steps: Some(4), ..Limits::default() };
+
+        let (outcome, output) = runner.run_with_limits("+.+.+.+.", b"", limits).unwrap();
+        assert_eq!(outcome, RunOutcome::StepLimit);
+        assert_eq!(output, [1, 2]);
+
+        let (outcome, output) = runner.run_with_limits("+.+.", b"", limits).unwrap();
+        assert_eq!(outcome, RunOutcome::Completed);
+        assert_eq!(output, [1, 2]);
+
+        let limits = Limits { pointer_limit: Some(2), ..Limits::default() };
+        let (outcome, _) = runner.run_with_limits(">>>+", b"", limits).unwrap();
+        assert_eq!(outcome, RunOutcome::Halted);
+    }
+
+    #[test]
+    fn programs_without_reads_take_the_fast_path() {
+        let mut runner = Runner::new(8);
+
+        assert_eq!(runner.run("++.", b"ignored").unwrap(), [2]);
+        assert!(runner.machine.ran_without_input());
+
+        assert_eq!(runner.run(",+.", b"a").unwrap(), b"b");
+        assert!(!runner.machine.ran_without_input());
+    }
+}
@@ -1,157 +1,1831 @@
 use std::env;
-use std::io::Read;
+use std::io::{IsTerminal, Read, Write};
 use std::fs::File;
+#[cfg(feature = "raw-tty")]
+use std::process::{Command, Stdio};
+#[cfg(feature = "raw-tty")]
+use std::sync::Mutex;
+use std::time::Instant;
 
-/// Opcodes determined by the lexer
-#[derive(Debug)]
-#[derive(Clone)]
-enum OpCode {
-    IncrementPointer,
-    DecrementPointer,
-    Increment,
-    Decrement,
-    Write,
-    Read,
-    LoopBegin,
-    LoopEnd,
-}
-
-#[derive(Debug)]
-#[derive(Clone)]
-enum Instruction {
-    IncrementPointer,
-    DecrementPointer,
-    Increment,
-    Decrement,
-    Write,
-    Read,
-    Loop(Vec<Instruction>)
-}
-
-/// Lexer turns the source code into a sequence of opcodes
-fn lex(source: String) -> Vec<OpCode> {
-    let mut operations = Vec::new();
-
-    for symbol in source.chars() {
-        let op = match symbol {
-            '>' => Some(OpCode::IncrementPointer),
-            '<' => Some(OpCode::DecrementPointer),
-            '+' => Some(OpCode::Increment),
-            '-' => Some(OpCode::Decrement),
-            '.' => Some(OpCode::Write),
-            ',' => Some(OpCode::Read),
-            '[' => Some(OpCode::LoopBegin),
-            ']' => Some(OpCode::LoopEnd),
-            _ => None
+use bfi_rs::{dead_loops, endless_loops, expand_macros_with_limit, flatten, flatten_with_positions, fold_output, instruction_count, lex, lex_strict, optimize, parse, parse_with_max_depth, reads_input, repair, sanitize_for_terminal, to_json, SparseTape, unwritten_reads, Instruction, Io, LexOptions, Machine, Op, Repair, PASSES};
+#[cfg(feature = "raw-tty")]
+use bfi_rs::{enter_cbreak, restore_terminal, CbreakInput, Terminal};
+
+/// Prints every instruction with a plain description, loop bodies indented by nesting level
+fn explain(instructions: &[(usize, Instruction)], depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for (_, instr) in instructions {
+        let (symbol, description) = match instr {
+            Instruction::IncrementPointer => (">", "move pointer right".to_string()),
+            Instruction::DecrementPointer => ("<", "move pointer left".to_string()),
+            Instruction::Increment => ("+", "increment current cell".to_string()),
+            Instruction::Decrement => ("-", "decrement current cell".to_string()),
+            Instruction::Write => (".", "output current cell".to_string()),
+            Instruction::Read => (",", "read input into current cell".to_string()),
+            Instruction::SwitchTape => ("$", "switch to the other tape".to_string()),
+            Instruction::Duplicate => ("^", "copy current cell into the next cell".to_string()),
+            Instruction::Loop(nested_instructions) => {
+                println!("{}[  begin loop while current cell nonzero", indent);
+                explain(nested_instructions, depth + 1);
+                ("]", "end loop".to_string())
+            },
+            Instruction::Clear => ("[-]", "set current cell to zero".to_string()),
+            Instruction::MultiplyAdd { offset, factor } => {
+                ("*", format!("add current cell times {} to the cell at offset {}", factor, offset))
+            },
+            Instruction::Add(amount) if *amount > 127 => ("-", format!("subtract {} from current cell", amount.wrapping_neg())),
+            Instruction::Add(amount) => ("+", format!("add {} to current cell", amount)),
+            Instruction::Move(offset) if *offset < 0 => ("<", format!("move pointer {} left", -offset)),
+            Instruction::Move(offset) => (">", format!("move pointer {} right", offset)),
+            Instruction::ClearRange { start_offset, count } => {
+                ("[-]", format!("set {} cells starting at offset {} to zero", count, start_offset))
+            },
+            Instruction::Emit(bytes) => (".", format!("output {:?}", String::from_utf8_lossy(bytes))),
         };
 
-        // Non-opcode characters are simply comments
-        match op {
-            Some(op) => operations.push(op),
-            None => ()
-        }
+        println!("{}{}  {}", indent, symbol, description);
     }
+}
 
-    operations
+/// Prints every operation with its address, a mnemonic and its operands, addresses in hex
+///
+/// Jumps show the address execution continues at.
+fn disasm(ops: &[Op]) {
+    for (address, op) in ops.iter().enumerate() {
+        let (mnemonic, operands) = match op {
+            Op::IncrementPointer => ("right", String::new()),
+            Op::DecrementPointer => ("left", String::new()),
+            Op::Increment => ("inc", String::new()),
+            Op::Decrement => ("dec", String::new()),
+            Op::Write => ("out", String::new()),
+            Op::Read => ("in", String::new()),
+            Op::LoopBegin(end) => ("jz", format!("{:04x}", end + 1)),
+            Op::LoopEnd(begin) => ("jnz", format!("{:04x}", begin + 1)),
+            Op::Clear => ("clear", String::new()),
+            Op::MultiplyAdd { offset, factor } => ("muladd", format!("{}, {}", offset, factor)),
+            Op::Add(amount) => ("add", amount.to_string()),
+            Op::Move(offset) => ("move", offset.to_string()),
+            Op::ClearRange { start_offset, count } => ("clearrange", format!("{}, {}", start_offset, count)),
+            Op::SwitchTape => ("switch", String::new()),
+            Op::Duplicate => ("dup", String::new()),
+            Op::Emit(bytes) => ("emit", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")),
+        };
+
+        let line = format!("{:04x}  {:<10} {}", address, mnemonic, operands);
+        println!("{}", line.trim_end());
+    }
 }
 
-fn parse(opcodes: Vec<OpCode>) -> Vec<Instruction> {
-    let mut program: Vec<Instruction> = Vec::new();
-    let mut loop_stack = 0;
-    let mut loop_start = 0;
+/// Names the idiom a loop body follows, along with how far it moves the pointer, if that is
+/// always the same, and whether it changes the cell it is guarded by
+fn classify_loop(body: &[(usize, Instruction)]) -> (&'static str, Option<isize>, bool) {
+    let mut offset = Some(0);
+    let mut changes_guard = false;
+    let mut origin_change = 0;
+    let mut changes_others = false;
+    let mut only_arithmetic = true;
 
-    for (i, op) in opcodes.iter().enumerate() {
-        if loop_stack == 0 {
-            let instr = match op {
-                OpCode::IncrementPointer => Some(Instruction::IncrementPointer),
-                OpCode::DecrementPointer => Some(Instruction::DecrementPointer),
-                OpCode::Increment => Some(Instruction::Increment),
-                OpCode::Decrement => Some(Instruction::Decrement),
-                OpCode::Write => Some(Instruction::Write),
-                OpCode::Read => Some(Instruction::Read),
+    for (_, instr) in body {
+        let current = match offset {
+            Some(current) => current,
+            None => {
+                // Anything might be the guard once the pointer is lost
+                changes_guard |= !matches!(instr, Instruction::Write);
+                continue;
+            },
+        };
 
-                OpCode::LoopBegin => {
-                    loop_start = i;
-                    loop_stack += 1;
-                    None
-                },
+        match instr {
+            Instruction::IncrementPointer => offset = Some(current + 1),
+            Instruction::DecrementPointer => offset = Some(current - 1),
+            Instruction::Increment | Instruction::Decrement if current == 0 => {
+                changes_guard = true;
+                origin_change += if let Instruction::Increment = instr { 1 } else { -1 };
+            },
+            Instruction::Increment | Instruction::Decrement => changes_others = true,
+            Instruction::Loop(nested_instructions) => {
+                only_arithmetic = false;
+                changes_guard |= current == 0;
+
+                let (_, nested_offset, _) = classify_loop(nested_instructions);
+                offset = if nested_offset == Some(0) { offset } else { None };
+            },
+            _ => {
+                only_arithmetic = false;
+                changes_guard |= current == 0 && !matches!(instr, Instruction::Write);
+            },
+        }
+    }
 
-                OpCode::LoopEnd => panic!("Loop ending at #{} has no beginning", i),
+    let idiom = match (only_arithmetic, offset, origin_change, changes_others) {
+        (true, Some(0), -1 | 1, false) => "clear",
+        (true, Some(0), -1, true) => "copy",
+        (true, Some(offset), 0, false) if offset != 0 => "scan",
+        _ => "other",
+    };
+
+    (idiom, offset, changes_guard)
+}
+
+/// Prints a line for every loop, with its position in the program, inner loops after the loop
+/// they are in
+fn loop_report(instructions: &[(usize, Instruction)]) {
+    for (position, instr) in instructions {
+        if let Instruction::Loop(nested_instructions) = instr {
+            let (idiom, offset, changes_guard) = classify_loop(nested_instructions);
+
+            let balance = match offset {
+                Some(0) => "balanced".to_string(),
+                Some(offset) => format!("moves {}", offset),
+                None => "unbalanced".to_string(),
             };
+            let guard = if changes_guard { "changes guard" } else { "keeps guard" };
+            println!("#{} {}: {}, {}", position, idiom, balance, guard);
 
-            match instr {
-                Some(instr) => program.push(instr),
-                None => ()
-            }
+            loop_report(nested_instructions);
+        }
+    }
+}
+
+/// Writer that keeps a 64-bit FNV-1a hash of everything written to it
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program several times with a fresh memory and the same input, printing how long
+/// every run took and the mean
+///
+/// Every run starts on a machine set up the same way as a single run, and writes to the same
+/// output. With `--repeat-collect`, the outputs are kept apart from the timings and written
+/// together once all runs are done.
+fn repeat_runs(ops: &[Op], positions: &[usize], options: &Options, runs: usize) {
+    let mut input = Vec::new();
+    open_inputs(options).read_to_end(&mut input).expect("Failed to read input");
+
+    let mut output = wrap_output(open_output(options), options);
+    let mut total = 0.0;
+    let mut outputs: Vec<Vec<u8>> = Vec::new();
+
+    for run in 1..=runs {
+        outputs.push(Vec::new());
+
+        let run_output: Box<dyn Write> = if options.repeat_collect {
+            Box::new(outputs.last_mut().unwrap())
+        } else if options.repeat_quiet && run < runs {
+            Box::new(std::io::sink())
         } else {
-            match op {
-                OpCode::LoopBegin => {
-                    loop_stack += 1;
-                },
-                OpCode::LoopEnd => {
-                    loop_stack -= 1;
+            Box::new(&mut output)
+        };
 
-                    if loop_stack == 0 {
-                        program.push(Instruction::Loop(parse(opcodes[loop_start+1..i].to_vec())));
-                    }
-                },
-                _ => (),
+        let mut io = program_io(&input[..], run_output, options);
+        let mut machine = setup_machine(options);
+        let started = Instant::now();
+
+        run_to_end(&mut machine, ops, positions, &mut io, options);
+        drop(io);
+
+        let seconds = started.elapsed().as_secs_f64();
+        total += seconds;
+        eprintln!("Run {}: {:.3} ms", run, seconds * 1000.0);
+    }
+
+    eprintln!("Mean: {:.3} ms", total * 1000.0 / runs as f64);
+
+    if options.repeat_collect {
+        output.write_all(&outputs.join(options.repeat_delimiter.as_bytes())).expect("Failed to write output");
+    }
+
+    output.flush().expect("Failed to flush output");
+}
+
+/// Runs the program to its end, stopping with an error at the step and time limits and, under
+/// `--sandbox`, in a loop that never ends
+///
+/// This is the run loop of the modes that don't look at the machine between steps. Errors are
+/// reported at the source positions of the operations.
+fn run_to_end<T: bfi_rs::Tape>(machine: &mut Machine<T>, ops: &[Op], positions: &[usize], io: &mut Io<'_>, options: &Options) {
+    let endless = if options.sandbox { endless_loops(ops) } else { Vec::new() };
+    let reads = reads_input(ops);
+    let started = Instant::now();
+
+    loop {
+        let mut steps = options.checkpoint_interval;
+
+        if let Some(max_steps) = options.max_steps {
+            if machine.steps >= max_steps {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("Step limit of {} reached", max_steps);
+                exit(1);
+            }
+
+            steps = steps.min(max_steps - machine.steps);
+        }
+
+        let ran = machine.run_steps_reading(ops, steps, io, reads);
+        match ran {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(error) => {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("{}", error.at_source(positions));
+                exit(1);
+            },
+        }
+
+        let stuck = endless.iter().find(|(begin, end)| (begin + 1..=*end).contains(&machine.program_counter));
+        if let Some((begin, _)) = stuck {
+            io.output.flush().expect("Failed to flush output");
+            eprintln!("Loop at #{} never ends, its body only moves the pointer back to the same cell", positions[*begin]);
+            exit(1);
+        }
+
+        if let Some(timeout) = options.timeout {
+            if started.elapsed().as_secs_f64() > timeout {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("Time limit of {} seconds reached", timeout);
+                exit(1);
             }
         }
     }
 
-    if loop_stack != 0 {
-        panic!("Loop that starts at #{} has no matching ending!", loop_start);
+    io.output.flush().expect("Failed to flush output");
+}
+
+/// Stops with an error when one of the options a mode can't honour is given, naming the first
+fn reject_unsupported(mode: &str, unsupported: &[(&str, bool)]) {
+    if let Some((option, _)) = unsupported.iter().find(|(_, given)| *given) {
+        eprintln!("{} can't be used with {}", option, mode);
+        exit(1);
+    }
+}
+
+/// Wraps the source in a shell script that gives it to the interpreter through a here-document
+///
+/// The here-document is opened as file descriptor 3, so the program still reads its input from
+/// standard input. The options that change how the source is read or run are passed on, and
+/// the script's own arguments come after them.
+fn shell_script(source: &str, options: &Options) -> String {
+    // The delimiter must not appear as a line of the source itself
+    let mut delimiter = String::from("BFI_RS_EOF");
+    while source.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+
+    let mut dialect = String::new();
+    for (flag, given) in [("-O", options.optimize), ("--paren-comments", options.paren_comments), ("--ext-dual-tape", options.ext_dual_tape), ("--ext-dup", options.ext_dup), ("--strict-chars", options.strict_chars)] {
+        if given {
+            dialect.push_str(flag);
+            dialect.push(' ');
+        }
+    }
+
+    // Single quotes keep the shell away from the character, only a quote itself needs more
+    if let Some(delim) = options.comment_delim {
+        let quoted = if delim == '\'' { "\"'\"".to_string() } else { format!("'{}'", delim) };
+        dialect.push_str(&format!("--comment-delim {} ", quoted));
     }
 
-    program
+    let newline = if source.ends_with('\n') { "" } else { "\n" };
+    format!("#!/bin/sh\nexec bfi_rs {}\"$@\" /dev/fd/3 3<<'{}'\n{}{}{}\n", dialect, delimiter, source, newline, delimiter)
 }
 
-/// Executes a program that was previously parsed
-fn run(instructions: &Vec<Instruction>, bf_memory: &mut Vec<u8>, data_pointer: &mut usize) {
-    for instr in instructions {
-        match instr {
-            Instruction::IncrementPointer => *data_pointer += 1,
-            Instruction::DecrementPointer => *data_pointer -= 1,
-            Instruction::Increment => bf_memory[*data_pointer] += 1,
-            Instruction::Decrement => bf_memory[*data_pointer] -= 1,
-            Instruction::Write => print!("{}", bf_memory[*data_pointer] as char),
-            Instruction::Read => {
-                let mut input: [u8; 1] = [0; 1];
-                std::io::stdin().read_exact(&mut input).expect("failed to read stdin");
-                bf_memory[*data_pointer] = input[0];
+/// Writer that replaces every byte by the entry for it in a table before passing it on
+struct MappedWriter<W: Write> {
+    table: Vec<u8>,
+    inner: W,
+}
+
+impl<W: Write> Write for MappedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mapped: Vec<u8> = buf.iter().map(|byte| self.table[*byte as usize]).collect();
+        self.inner.write_all(&mapped)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer that passes every byte on to two writers
+struct TeeWriter<A: Write, B: Write> {
+    inner: A,
+    copy: B,
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write_all(buf)?;
+        self.copy.write_all(buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        self.copy.flush()
+    }
+}
+
+/// Writes every cell of the tape in use that changed since the last snapshot as a
+/// `number value` line, then updates the snapshot
+fn log_writes(log: &mut impl Write, snapshot: &mut [u8], machine: &Machine) {
+    for (i, (old, new)) in snapshot.iter_mut().zip(&machine.bf_memory).enumerate() {
+        if old != new {
+            writeln!(log, "{} {}", machine.cell_number(i), new).expect("Failed to write trace");
+            *old = *new;
+        }
+    }
+}
+
+/// Number of cells of a sparse tape, as many as the pointer can address
+const SPARSE_CELLS: usize = usize::MAX;
+
+/// Input and output for a run of the program, with the echo, the prompt and the end of input
+/// handling the options ask for
+fn program_io<'a>(input: impl Read + 'a, output: impl Write + 'a, options: &Options) -> Io<'a> {
+    Io::new(input, output)
+        .echo_input(options.echo_input)
+        .prompt(options.prompt.clone().unwrap_or_default())
+        .strict_eof(options.strict_eof)
+}
+
+/// Opens the input files as a single stream, or standard input when there are none
+fn open_inputs(options: &Options) -> Box<dyn Read> {
+    if options.inputs.is_empty() {
+        return Box::new(std::io::stdin());
+    }
+
+    options.inputs.iter().fold(Box::new(std::io::empty()), |input, path| {
+        let file = File::open(path).expect("Input file not found");
+        Box::new(input.chain(file))
+    })
+}
+
+/// Runs the program on a sparse tape of `SPARSE_CELLS` cells
+///
+/// Only the input and output options, the limits and the pointer reports apply to these runs,
+/// the other options need a dense memory and are rejected before.
+fn run_sparse(ops: &[Op], positions: &[usize], options: &Options) {
+    let output = wrap_output(open_output(options), options);
+    let mut io = program_io(open_inputs(options), output, options);
+    let mut machine = Machine::with_tape(SparseTape::new(SPARSE_CELLS));
+
+    run_to_end(&mut machine, ops, positions, &mut io, options);
+    drop(io);
+
+    if options.show_pointer {
+        eprintln!("Data pointer: {}", machine.data_pointer);
+    }
+
+    if options.report_peak {
+        eprintln!("Peak data pointer: {}", machine.peak_pointer);
+    }
+}
+
+/// Sets up the machine the program starts on, from a checkpoint or with a fresh memory, with
+/// the seed, the start pointer, the soft limit and the band applied
+///
+/// Cells given for the other options are checked to lie inside the memory as well.
+fn setup_machine(options: &Options) -> Machine {
+    let mut machine = match &options.resume {
+        Some(path) => Machine::load(path).expect("Failed to load checkpoint"),
+        None => Machine::new(if options.bidirectional_tape { 2048 } else { 1024 }),
+    };
+
+    // Half of the memory lies left of the cell the program starts on
+    if options.bidirectional_tape {
+        machine.origin = machine.bf_memory.len() / 2;
+
+        if options.resume.is_none() {
+            machine.data_pointer = machine.origin;
+            machine.peak_pointer = machine.origin;
+        }
+    }
+
+    if let Some(path) = &options.seed_from_file {
+        let seed = std::fs::read(path).expect("Seed file not found");
+
+        // The seed starts on the first cell the program sees, bytes that don't fit are left out
+        let origin = machine.origin;
+        let length = seed.len().min(machine.bf_memory.len() - origin);
+        machine.bf_memory[origin..origin + length].copy_from_slice(&seed[..length]);
+    }
+
+    if let Some(start_pointer) = options.start_pointer {
+        let start = cell_index(&machine, start_pointer, "Start pointer");
+        machine.data_pointer = start;
+        machine.peak_pointer = machine.peak_pointer.max(start);
+    }
+
+    // Past the memory the limit can never be crossed, left of it it always is
+    if let Some(soft_limit) = options.soft_limit {
+        machine.pointer_limit = machine.origin.saturating_add_signed(soft_limit);
+    }
+
+    if let Some(band) = &options.band {
+        let (low, high) = match band.split_once(':').map(|(low, high)| (low.parse(), high.parse())) {
+            Some((Ok(low), Ok(high))) if low <= high => (low, high),
+            _ => usage(),
+        };
+
+        let (first, last) = match (machine.cell_index(low), machine.cell_index(high)) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                eprintln!("Band {}:{} is outside of the {} memory cells", low, high, machine.bf_memory.len());
+                exit(1);
             },
-            Instruction::Loop(nested_instructions) => {
-                while bf_memory[*data_pointer] != 0 {
-                    run(&nested_instructions, bf_memory, data_pointer)
-                }
+        };
+
+        if !(first..=last).contains(&machine.data_pointer) {
+            eprintln!("Data pointer {} is outside of the band {}:{}", machine.cell_number(machine.data_pointer), low, high);
+            exit(1);
+        }
+
+        machine.band = Some((first, last));
+    }
+
+    if let Some(watch) = options.watch {
+        cell_index(&machine, watch, "Watched cell");
+    }
+
+    if let Some(guard_cell) = options.guard_cell {
+        cell_index(&machine, guard_cell, "Guard cell");
+    }
+
+    if let Some(log_cell) = options.log_cell {
+        cell_index(&machine, log_cell, "Logged cell");
+    }
+
+    machine
+}
+
+/// Index in the memory of a cell given on the command line, counted from the cell the program
+/// starts on, stopping with an error naming `what` when it lies outside of the memory
+fn cell_index(machine: &Machine, number: isize, what: &str) -> usize {
+    match machine.cell_index(number) {
+        Some(index) => index,
+        None => {
+            eprintln!("{} {} is outside of the {} memory cells", what, number, machine.bf_memory.len());
+            exit(1);
+        },
+    }
+}
+
+/// Opens where the output goes when it isn't collected, a file or standard output
+fn open_output(options: &Options) -> Box<dyn Write> {
+    if let Some(path) = &options.output_file {
+        Box::new(std::io::BufWriter::new(File::create(path).expect("Failed to create output file")))
+    } else if options.sanitize_output {
+        sanitize_for_terminal(std::io::stdout(), std::io::stdout().is_terminal())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+/// Adds the copy for `--tee` and the translation of `--output-map` to the output
+fn wrap_output<'a>(output: Box<dyn Write + 'a>, options: &Options) -> Box<dyn Write + 'a> {
+    // The copy gets the bytes the program wrote, even where a terminal is shown them escaped
+    let output: Box<dyn Write + 'a> = match &options.tee {
+        Some(path) => {
+            let copy = std::io::BufWriter::new(File::create(path).expect("Failed to create tee file"));
+            Box::new(TeeWriter { inner: output, copy })
+        },
+        None => output,
+    };
+
+    match &options.output_map {
+        Some(path) => {
+            let table = std::fs::read(path).expect("Output map not found");
+            if table.len() != 256 {
+                eprintln!("Output map {} has {} entries instead of 256", path, table.len());
+                exit(1);
             }
+
+            Box::new(MappedWriter { table, inner: output })
+        },
+        None => output,
+    }
+}
+
+/// Opens the program at the path, or standard input for `-`
+///
+/// Compressed programs, with `--gzip` or a `.gz` file name, are decompressed as they are read.
+fn open_program(path: &str, options: &Options) -> Box<dyn Read> {
+    let file: Box<dyn Read> = if path == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(File::open(path).expect("Executable file not found"))
+    };
+
+    if options.gzip || path.ends_with(".gz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        file
+    }
+}
+
+/// Reads, parses and flattens another program the same way as the main one
+fn compile_file(path: &str, options: &Options) -> Vec<Op> {
+    let mut source = String::new();
+    open_program(path, options).read_to_string(&mut source).expect("Failed to read executable file");
+
+    if options.macros {
+        source = expand_macros_with_limit(&source, options.max_source_bytes.unwrap_or(usize::MAX)).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            exit(1);
+        });
+    }
+
+    let opcodes = lex(source, options.lex_options());
+    let program = parse(opcodes).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(1);
+    });
+
+    flatten(&if options.optimize { optimize(program) } else { program })
+}
+
+/// Runs operations on a memory set up like the one of a single run, returning what they wrote
+/// and how they stopped
+fn run_case(ops: &[Op], input: &[u8], options: &Options) -> (Vec<u8>, &'static str) {
+    let mut output = Vec::new();
+    let mut io = Io::new(input, &mut output).strict_eof(options.strict_eof);
+    let mut machine = setup_machine(options);
+
+    let outcome = match machine.run_steps(ops, options.max_steps.unwrap_or(10_000_000), &mut io) {
+        Ok(false) => "finished",
+        Ok(true) => "ran out of steps",
+        Err(_) => "stopped with an error",
+    };
+
+    drop(io);
+    (output, outcome)
+}
+
+/// Runs the program on every input of a single byte and prints what it wrote for each, along
+/// with how it stopped when that wasn't by finishing
+fn truth_table(ops: &[Op], options: &Options) {
+    for byte in 0..=u8::MAX {
+        let (output, outcome) = run_case(ops, &[byte], options);
+
+        match outcome {
+            "finished" => println!("{:3} -> \"{}\"", byte, output.escape_ascii()),
+            _ => println!("{:3} -> \"{}\", {}", byte, output.escape_ascii(), outcome),
+        }
+    }
+}
+
+/// Runs both programs on the same inputs and reports the first input they behave differently
+/// on, which only shows they agree on those inputs rather than proving them equivalent
+///
+/// The first input is empty, the others are up to 16 pseudo-random bytes, the same on every run.
+fn check_equivalence(programs: [(&str, &[Op]); 2], options: &Options) {
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for case in 0..options.equiv_inputs {
+        let length = if case == 0 { 0 } else { (next() % 16 + 1) as usize };
+        let input: Vec<u8> = (0..length).map(|_| next() as u8).collect();
+
+        let results = programs.map(|(_, ops)| run_case(ops, &input, options));
+
+        if results[0] != results[1] {
+            println!("Programs differ for input \"{}\":", input.escape_ascii());
+
+            for ((name, _), (output, outcome)) in programs.iter().zip(&results) {
+                println!("  {}: \"{}\", {}", name, output.escape_ascii(), outcome);
+            }
+
+            exit(1);
+        }
+    }
+
+    println!("Programs wrote the same output for all {} inputs", options.equiv_inputs);
+}
+
+/// Runs the program unoptimized and then with each optimization pass and the output folding on
+/// its own, all with the same input, and reports every pass that makes it write something else
+/// or stop differently
+fn verify_passes(program: &[(usize, Instruction)], input: &[u8], options: &Options) {
+    let baseline = run_case(&flatten(program), input, options);
+    println!("unoptimized: wrote \"{}\", {}", baseline.0.escape_ascii(), baseline.1);
+
+    let mut diverged = false;
+    let mut compare = |name: &str, passed: Vec<(usize, Instruction)>| {
+        let (output, outcome) = run_case(&flatten(&passed), input, options);
+
+        if (&output, outcome) == (&baseline.0, baseline.1) {
+            println!("{}: same output", name);
+        } else {
+            println!("{}: wrote \"{}\", {}, instead of \"{}\", {}", name, output.escape_ascii(), outcome, baseline.0.escape_ascii(), baseline.1);
+            diverged = true;
         }
+    };
+
+    for (name, pass) in PASSES {
+        compare(name, pass(program.to_vec()));
+    }
+
+    // Folding assumes a zeroed memory without a band, and has the cells right of the start
+    if starts_on_plain_memory(options) {
+        let machine = setup_machine(options);
+        compare("fold output", fold_output(program.to_vec(), machine.bf_memory.len() - machine.data_pointer));
+    } else {
+        println!("fold output: not checked, the memory doesn't start out zeroed and without a band");
+    }
+
+    if diverged {
+        exit(1);
+    }
+}
+
+/// Draws the tape in use for `--frames`, as rows of 16 cells with the current cell in brackets
+///
+/// Only the rows from the first to the last cell that isn't zero or holds the pointer are drawn,
+/// each starting with the number of its first cell and ending with the cells as text, where
+/// bytes that aren't printable are escaped like `\n` or `\x00`.
+fn render_frame(machine: &Machine) -> String {
+    let cells = &machine.bf_memory;
+    let pointer = machine.data_pointer;
+    let first = cells.iter().position(|cell| *cell != 0).unwrap_or(pointer).min(pointer);
+    let last = cells.iter().rposition(|cell| *cell != 0).unwrap_or(pointer).max(pointer);
+
+    let mut frame = format!("Step {}\n", machine.steps);
+    for row in (first - first % 16..=last).step_by(16) {
+        let end = cells.len().min(row + 16);
+        let line: String = (row..end).map(|i| {
+            if i == pointer { format!("[{:3}]", cells[i]) } else { format!(" {:3} ", cells[i]) }
+        }).collect();
+
+        frame.push_str(&format!("{:6}:{}  {}\n", machine.cell_number(row), line, cells[row..end].escape_ascii()));
+    }
+
+    frame
+}
+
+/// Terminal settings that `--raw-tty` changed, to put back before the process ends
+#[cfg(feature = "raw-tty")]
+static SAVED_TTY: Mutex<Option<String>> = Mutex::new(None);
+
+/// The terminal at standard input, set up through `stty`
+#[cfg(feature = "raw-tty")]
+struct Stty;
+
+#[cfg(feature = "raw-tty")]
+impl Stty {
+    /// Runs `stty` on the terminal at standard input, returning what it printed
+    fn run(args: &[&str]) -> Option<String> {
+        let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+#[cfg(feature = "raw-tty")]
+impl Terminal for Stty {
+    fn settings(&mut self) -> Option<String> {
+        Stty::run(&["-g"])
+    }
+
+    fn apply(&mut self, settings: &[&str]) -> bool {
+        Stty::run(settings).is_some()
+    }
+}
+
+/// Puts the terminal into cbreak mode, where every keypress is read right away and not
+/// echoed, returning the input to read the keys from
+///
+/// The terminal is put back by `restore_tty`, which `exit`, the panic hook and the end of
+/// `main` all call. Ctrl-C is read as a key in this mode, the input puts the terminal back
+/// when it comes and ends the process the way the signal would have.
+#[cfg(feature = "raw-tty")]
+fn enter_raw_tty(input: Box<dyn Read>) -> Box<dyn Read> {
+    let saved = match enter_cbreak(&mut Stty) {
+        Ok(saved) => saved,
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(1);
+        },
+    };
+
+    *SAVED_TTY.lock().unwrap() = Some(saved.clone());
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_tty();
+        default_hook(info);
+    }));
+
+    // 130 is what shells report for a process ended by Ctrl-C
+    Box::new(CbreakInput::new(input, Stty, saved, || {
+        SAVED_TTY.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        exit(130);
+    }))
+}
+
+#[cfg(not(feature = "raw-tty"))]
+fn enter_raw_tty(_input: Box<dyn Read>) -> Box<dyn Read> {
+    eprintln!("--raw-tty needs bfi_rs built with the raw-tty feature");
+    exit(1);
+}
+
+/// Puts back the terminal settings saved by `enter_raw_tty`, if there are any
+fn restore_tty() {
+    #[cfg(feature = "raw-tty")]
+    if let Some(saved) = SAVED_TTY.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+        restore_terminal(&mut Stty, &saved);
+    }
+}
+
+/// Ends the process, first putting the terminal back the way it was
+fn exit(code: i32) -> ! {
+    restore_tty();
+    std::process::exit(code)
+}
+
+/// Waits for Enter before `--watch` lets the program go on, when someone is looking at the
+/// messages on a terminal
+///
+/// Enter is read from the terminal itself, as standard input may belong to the program.
+fn pause_at_watch(io: &mut Io<'_>) {
+    if !std::io::stderr().is_terminal() {
+        return;
+    }
+
+    let mut terminal = match File::open("/dev/tty") {
+        Ok(terminal) => terminal,
+        Err(_) => return,
+    };
+
+    // Output so far should be visible while paused
+    io.output.flush().expect("Failed to flush output");
+    eprint!("Press Enter to continue");
+
+    let mut byte = [0];
+    while terminal.read(&mut byte).is_ok_and(|read| read == 1 && byte[0] != b'\n') {}
+}
+
+/// Stops with an error when the source is longer than `--max-source-bytes` allows
+fn check_source_size(length: usize, max_source_bytes: Option<usize>) {
+    if let Some(max_source_bytes) = max_source_bytes {
+        if length > max_source_bytes {
+            eprintln!("Program is longer than the limit of {} bytes", max_source_bytes);
+            exit(1);
+        }
+    }
+}
+
+/// Whether the program starts on a zeroed memory without a band, the one the output folding
+/// runs it on ahead of time
+fn starts_on_plain_memory(options: &Options) -> bool {
+    options.seed_from_file.is_none() && options.resume.is_none() && options.band.is_none()
+}
+
+/// Whether the output folding leaves the run the same
+///
+/// Folding runs the program ahead of time on a zeroed memory, which a seed file, a checkpoint,
+/// a band or a guard cell would all make differ from the real run. The options that report on
+/// the pointer, the cells or the steps while the program runs would miss the part run ahead of
+/// time, and the limits would not count it. Checkpoints would save a program counter into the
+/// folded program, which a run that resumes them without folding the same way can't continue.
+fn can_fold_output(options: &Options) -> bool {
+    let changes_the_run = !starts_on_plain_memory(options)
+        || options.checkpoint.is_some()
+        || options.guard_cell.is_some()
+        || options.report_peak
+        || options.soft_limit.is_some()
+        || options.emit_trace.is_some()
+        || options.watch.is_some()
+        || options.log_cell.is_some()
+        || options.tape_coverage
+        || options.asan
+        || options.max_steps.is_some()
+        || options.timeout.is_some()
+        || options.sandbox
+        || options.trace
+        || options.frames.is_some();
+
+    !changes_the_run
+}
+
+/// Settings given on the command line
+struct Options {
+    filename: String,
+    checkpoint: Option<String>,
+    checkpoint_interval: u64,
+    resume: Option<String>,
+    comment_delim: Option<char>,
+    trace: bool,
+    window: usize,
+    inputs: Vec<String>,
+    explain: bool,
+    watch: Option<isize>,
+    output_file: Option<String>,
+    optimize: bool,
+    show_pointer: bool,
+    start_pointer: Option<isize>,
+    echo_input: bool,
+    sandbox: bool,
+    max_steps: Option<u64>,
+    timeout: Option<f64>,
+    report_peak: bool,
+    soft_limit: Option<isize>,
+    soft_limit_strict: bool,
+    predict_output: bool,
+    tape_diff: bool,
+    band: Option<String>,
+    opt_report: bool,
+    seed_from_file: Option<String>,
+    emit_ast_json: bool,
+    tape_coverage: bool,
+    output_hash: bool,
+    macros: bool,
+    repair: bool,
+    time_phases: bool,
+    repeat: Option<usize>,
+    repeat_quiet: bool,
+    lint: bool,
+    emit_script: bool,
+    paren_comments: bool,
+    emit_trace: Option<String>,
+    ext_dual_tape: bool,
+    guard_cell: Option<isize>,
+    guard_from_start: bool,
+    loop_report: bool,
+    max_depth: Option<usize>,
+    output_map: Option<String>,
+    equiv: Option<String>,
+    equiv_inputs: usize,
+    log_cell: Option<isize>,
+    disasm: bool,
+    asan: bool,
+    tape: String,
+    bidirectional_tape: bool,
+    sanitize_output: bool,
+    prompt: Option<String>,
+    truth_table: bool,
+    print_size: bool,
+    ext_dup: bool,
+    fold_output: bool,
+    verify_opts: bool,
+    raw_tty: bool,
+    repeat_collect: bool,
+    repeat_delimiter: String,
+    max_source_bytes: Option<usize>,
+    tee: Option<String>,
+    strict_chars: bool,
+    frames: Option<u64>,
+    frames_dir: String,
+    strict_eof: bool,
+    gzip: bool,
+}
+
+impl Options {
+    /// Dialect the programs are written in
+    fn lex_options(&self) -> LexOptions {
+        LexOptions {
+            comment_delim: self.comment_delim,
+            paren_comments: self.paren_comments,
+            dual_tape: self.ext_dual_tape,
+            dup: self.ext_dup,
+        }
+    }
+}
+
+fn usage() -> ! {
+    println!("Usage: bfi_rs [options] 'some.bf'");
+    println!();
+    println!("Use - as the file to read the program from standard input.");
+    println!();
+    println!("Options:");
+    println!("  --checkpoint <path>            periodically save the machine state to <path>");
+    println!("  --checkpoint-interval <steps>  instructions executed between checkpoints (default 1000000)");
+    println!("  --resume <path>                continue from a previously saved checkpoint");
+    println!("  --comment-delim <char>         ignore everything after <char> up to the end of the line");
+    println!("  --trace                        print every operation and the cells around the pointer to stderr");
+    println!("  --window <n>                   cells shown on each side of the pointer when tracing (default 5)");
+    println!("  --input <path>                 read input from <path> instead of stdin, may be given multiple times");
+    println!("  --explain                      describe what each instruction does instead of running");
+    println!("  --watch <index>                stop at every change of the cell at <index> and report it to stderr");
+    println!("  --output-file <path>           write program output to <path> instead of stdout");
+    println!("  -O                             optimize the program before running it");
+    println!("  --show-pointer                 print the final data pointer position to stderr");
+    println!("  --start-pointer <n>            start with the data pointer at cell <n>");
+    println!("  --echo-input                   copy every byte read to the output, like a terminal");
+    println!("  --max-steps <n>                stop with an error after executing <n> operations");
+    println!("  --timeout <seconds>            stop with an error after running for <seconds>");
+    println!("  --sandbox                      limit untrusted programs to 100000000 steps, 10 seconds and plain opcodes");
+    println!("  --report-peak                  print the highest data pointer reached to stderr");
+    println!("  --soft-limit <n>               warn when the data pointer moves past cell <n>");
+    println!("  --soft-limit-strict            stop with an error instead of warning at the soft limit");
+    println!("  --predict-output               run without input and print the output as an escaped string");
+    println!("  --tape-diff                    print every cell the program changed to stderr");
+    println!("  --band <lo>:<hi>               wrap the data pointer around within cells <lo> to <hi>");
+    println!("  --opt-report                   with -O, print how much each optimization pass shrank the program");
+    println!("  --seed-from-file <path>        load the initial memory contents from the bytes of <path>");
+    println!("  --emit-ast-json                print the parsed program as JSON instead of running");
+    println!("  --tape-coverage                print how many cells the program accessed, and their range, to stderr");
+    println!("  --output-hash                  print the 64-bit FNV-1a hash of the output instead of the output");
+    println!("  --macros                       expand @name = body macro definitions at the top of the file");
+    println!("  --repair                       drop unmatched ] and close unmatched [ at the end, with a warning");
+    println!("  --time-phases                  print how long lexing, parsing, each optimization pass and running took");
+    println!("  --repeat <n>                   run the program n times on the same input and print the timings");
+    println!("  --repeat-quiet                 with --repeat, only show the output of the last run");
+    println!("  --lint                         warn about cells read before they are written, dead loops and missing input");
+    println!("  --emit-script                  print a shell script that runs the program with the source embedded");
+    println!("  --paren-comments               skip everything between ( and the matching ) as a comment");
+    println!("  --emit-trace <path>            write the cells changed between input and output operations to <path>");
+    println!("  --ext-dual-tape                make $ switch between two tapes, each with its own pointer");
+    println!("  --guard-cell <index>           stop with an error when an operation after the first writes to cell <index>");
+    println!("  --guard-from-start             with --guard-cell, guard the first operation as well");
+    println!("  --loop-report                  print how every loop moves the pointer and whether it is a known idiom");
+    println!("  --max-depth <n>                reject programs with loops nested more than n levels deep");
+    println!("  --output-map <path>            translate every output byte through the 256 bytes in <path>");
+    println!("  --equiv <other.bf>             check that <other.bf> writes the same output for generated inputs");
+    println!("  --equiv-inputs <n>             number of inputs --equiv tries, 100 by default");
+    println!("  --log-cell <index>             print every value cell <index> takes once the program ends");
+    println!("  --disasm                       print the flattened operations with their addresses and operands");
+    println!("  --asan                         warn about every access outside of the memory or band before it goes ahead");
+    println!("  --tape <kind>                  dense, the default, or sparse to only store cells that aren't zero");
+    println!("  --bidirectional-tape           start in the middle of the memory, so cells left of the start have negative numbers");
+    println!("  --sanitize-output              on a terminal, write bytes that aren't printable ASCII as \\xNN");
+    println!("  --prompt <text>                write <text> to the output before every read");
+    println!("  --truth-table                  run the program on every single byte input and print what it writes");
+    println!("  --print-size                   print the number of instructions, after optimizing with -O, instead of running");
+    println!("  --ext-dup                      make ^ copy the current cell into the next one");
+    println!("  --fold-output                  with -O, replace everything before the first read with the output it writes");
+    println!("  --verify-opts                  run the program unoptimized and with each optimization pass alone, and compare what they write");
+    #[cfg(feature = "raw-tty")]
+    println!("  --raw-tty                      give , every keypress right away instead of whole lines, when reading from a terminal");
+    println!("  --repeat-collect               with --repeat, print the output of every run after the last one");
+    println!("  --repeat-delimiter <text>      what --repeat-collect puts between the outputs (default a newline)");
+    println!("  --max-source-bytes <n>         refuse programs longer than n bytes, before reading any further");
+    println!("  --tee <path>                   also write the output to <path>");
+    println!("  --strict-chars                 refuse to run programs with characters other than opcodes and whitespace");
+    println!("  --frames <n>                   write a picture of the tape to a numbered file every n steps");
+    println!("  --frames-dir <dir>             directory --frames writes to (default frames)");
+    println!("  --strict-eof                   stop with an error at a read past the end of the input, instead of leaving the cell unchanged");
+    println!("  --gzip                         decompress the program with gzip before lexing, the default for files ending in .gz");
+    exit(1);
+}
+
+/// Parses the value following an option, showing the usage when it is missing or malformed
+fn option_value<'a, T: std::str::FromStr>(args: &mut impl Iterator<Item = &'a String>) -> T {
+    match args.next().map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => usage(),
+    }
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let mut filename = None;
+    let mut checkpoint = None;
+    let mut checkpoint_interval = 1_000_000;
+    let mut resume = None;
+    let mut comment_delim = None;
+    let mut trace = false;
+    let mut window = 5;
+    let mut inputs = Vec::new();
+    let mut explain = false;
+    let mut watch = None;
+    let mut output_file = None;
+    let mut optimize = false;
+    let mut show_pointer = false;
+    let mut start_pointer = None;
+    let mut echo_input = false;
+    let mut max_steps = None;
+    let mut timeout = None;
+    let mut sandbox = false;
+    let mut report_peak = false;
+    let mut soft_limit = None;
+    let mut soft_limit_strict = false;
+    let mut predict_output = false;
+    let mut tape_diff = false;
+    let mut band = None;
+    let mut opt_report = false;
+    let mut seed_from_file = None;
+    let mut emit_ast_json = false;
+    let mut tape_coverage = false;
+    let mut output_hash = false;
+    let mut macros = false;
+    let mut repair = false;
+    let mut time_phases = false;
+    let mut repeat = None;
+    let mut repeat_quiet = false;
+    let mut lint = false;
+    let mut emit_script = false;
+    let mut paren_comments = false;
+    let mut emit_trace = None;
+    let mut ext_dual_tape = false;
+    let mut guard_cell = None;
+    let mut guard_from_start = false;
+    let mut loop_report = false;
+    let mut max_depth = None;
+    let mut output_map = None;
+    let mut equiv = None;
+    let mut equiv_inputs = 100;
+    let mut log_cell = None;
+    let mut disasm = false;
+    let mut asan = false;
+    let mut tape = "dense".to_string();
+    let mut bidirectional_tape = false;
+    let mut sanitize_output = false;
+    let mut prompt = None;
+    let mut truth_table = false;
+    let mut print_size = false;
+    let mut ext_dup = false;
+    let mut fold_output = false;
+    let mut verify_opts = false;
+    let mut raw_tty = false;
+    let mut repeat_collect = false;
+    let mut repeat_delimiter = String::from("\n");
+    let mut max_source_bytes = None;
+    let mut tee = None;
+    let mut strict_chars = false;
+    let mut frames = None;
+    let mut frames_dir = String::from("frames");
+    let mut strict_eof = false;
+    let mut gzip = false;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--checkpoint" => checkpoint = Some(option_value(&mut args)),
+            "--checkpoint-interval" => {
+                checkpoint_interval = option_value(&mut args);
+
+                if checkpoint_interval == 0 {
+                    usage();
+                }
+            },
+            "--resume" => resume = Some(option_value(&mut args)),
+            "--comment-delim" => comment_delim = Some(option_value(&mut args)),
+            "--trace" => trace = true,
+            "--window" => window = option_value(&mut args),
+            "--input" => inputs.push(option_value(&mut args)),
+            "--explain" => explain = true,
+            "--watch" => watch = Some(option_value(&mut args)),
+            "--output-file" => output_file = Some(option_value(&mut args)),
+            "-O" => optimize = true,
+            "--show-pointer" => show_pointer = true,
+            "--start-pointer" => start_pointer = Some(option_value(&mut args)),
+            "--echo-input" => echo_input = true,
+            "--max-steps" => max_steps = Some(option_value(&mut args)),
+            "--timeout" => timeout = Some(option_value(&mut args)),
+            "--sandbox" => sandbox = true,
+            "--report-peak" => report_peak = true,
+            "--soft-limit" => soft_limit = Some(option_value(&mut args)),
+            "--soft-limit-strict" => soft_limit_strict = true,
+            "--predict-output" => predict_output = true,
+            "--tape-diff" => tape_diff = true,
+            "--band" => band = Some(option_value(&mut args)),
+            "--opt-report" => opt_report = true,
+            "--seed-from-file" => seed_from_file = Some(option_value(&mut args)),
+            "--emit-ast-json" => emit_ast_json = true,
+            "--tape-coverage" => tape_coverage = true,
+            "--output-hash" => output_hash = true,
+            "--macros" => macros = true,
+            "--repair" => repair = true,
+            "--time-phases" => time_phases = true,
+            "--repeat" => repeat = Some(option_value(&mut args)),
+            "--repeat-quiet" => repeat_quiet = true,
+            "--lint" => lint = true,
+            "--emit-script" => emit_script = true,
+            "--paren-comments" => paren_comments = true,
+            "--emit-trace" => emit_trace = Some(option_value(&mut args)),
+            "--ext-dual-tape" => ext_dual_tape = true,
+            "--guard-cell" => guard_cell = Some(option_value(&mut args)),
+            "--guard-from-start" => guard_from_start = true,
+            "--loop-report" => loop_report = true,
+            "--max-depth" => max_depth = Some(option_value(&mut args)),
+            "--output-map" => output_map = Some(option_value(&mut args)),
+            "--equiv" => equiv = Some(option_value(&mut args)),
+            "--equiv-inputs" => {
+                equiv_inputs = option_value(&mut args);
+
+                if equiv_inputs == 0 {
+                    usage();
+                }
+            },
+            "--log-cell" => log_cell = Some(option_value(&mut args)),
+            "--disasm" => disasm = true,
+            "--asan" => asan = true,
+            "--tape" => tape = option_value(&mut args),
+            "--bidirectional-tape" => bidirectional_tape = true,
+            "--sanitize-output" => sanitize_output = true,
+            "--prompt" => prompt = Some(option_value(&mut args)),
+            "--truth-table" => truth_table = true,
+            "--print-size" => print_size = true,
+            "--ext-dup" => ext_dup = true,
+            "--fold-output" => fold_output = true,
+            "--verify-opts" => verify_opts = true,
+            "--raw-tty" => raw_tty = true,
+            "--repeat-collect" => repeat_collect = true,
+            "--repeat-delimiter" => repeat_delimiter = option_value(&mut args),
+            "--max-source-bytes" => max_source_bytes = Some(option_value(&mut args)),
+            "--tee" => tee = Some(option_value(&mut args)),
+            "--strict-chars" => strict_chars = true,
+            "--frames" => {
+                let every = option_value(&mut args);
+
+                if every == 0 {
+                    usage();
+                }
+
+                frames = Some(every);
+            },
+            "--frames-dir" => frames_dir = option_value(&mut args),
+            "--strict-eof" => strict_eof = true,
+            "--gzip" => gzip = true,
+            _ if arg.starts_with("--") || filename.is_some() => usage(),
+            _ => filename = Some(arg.clone()),
+        }
+    }
+
+    // Untrusted programs only get the plain language on the bounded memory
+    if sandbox && (ext_dual_tape || ext_dup || tape != "dense") {
+        eprintln!("--sandbox does not allow extension opcodes or other tapes");
+        exit(1);
+    }
+
+    // A band would wrap the pointer around where the sandbox stops it with an error
+    if sandbox && band.is_some() {
+        eprintln!("--sandbox does not allow --band");
+        exit(1);
+    }
+
+    // Strict programs have no room for comments, so asking for them too is a mistake
+    if strict_chars && (comment_delim.is_some() || paren_comments) {
+        eprintln!("--strict-chars does not allow --comment-delim or --paren-comments");
+        exit(1);
+    }
+
+    Options {
+        filename: filename.unwrap_or_else(|| usage()),
+        checkpoint,
+        checkpoint_interval,
+        resume,
+        comment_delim,
+        trace,
+        window,
+        inputs,
+        explain,
+        watch,
+        output_file,
+        optimize,
+        show_pointer,
+        start_pointer,
+        echo_input,
+        // Limits given explicitly take precedence over the sandbox defaults
+        sandbox,
+        max_steps: max_steps.or(if sandbox { Some(100_000_000) } else { None }),
+        timeout: timeout.or(if sandbox { Some(10.0) } else { None }),
+        report_peak,
+        soft_limit,
+        soft_limit_strict,
+        predict_output,
+        tape_diff,
+        band,
+        opt_report,
+        seed_from_file,
+        emit_ast_json,
+        tape_coverage,
+        output_hash,
+        macros,
+        repair,
+        time_phases,
+        repeat,
+        repeat_quiet,
+        lint,
+        emit_script,
+        paren_comments,
+        emit_trace,
+        ext_dual_tape,
+        guard_cell,
+        guard_from_start,
+        loop_report,
+        max_depth,
+        output_map,
+        equiv,
+        equiv_inputs,
+        log_cell,
+        disasm,
+        asan,
+        tape,
+        bidirectional_tape,
+        sanitize_output,
+        prompt,
+        truth_table,
+        print_size,
+        ext_dup,
+        fold_output,
+        verify_opts,
+        raw_tty,
+        repeat_collect,
+        repeat_delimiter,
+        max_source_bytes,
+        tee,
+        strict_chars,
+        frames,
+        frames_dir,
+        strict_eof,
+        gzip,
     }
 }
 
 fn main() {
     // Determine which file to execute
-    let args: Vec<String> = env::args().collect();
-    
-    if args.len() != 2 {
-        println!("Usage: bfi_rs 'some.bf'");
-        std::process::exit(1);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args);
+
+    // Read file, only up to the first byte past the size limit
+    let mut bytes = Vec::new();
+    let limit = options.max_source_bytes.map_or(u64::MAX, |max_source_bytes| max_source_bytes as u64 + 1);
+
+    open_program(&options.filename, &options).take(limit).read_to_end(&mut bytes).expect("Failed to read executable file");
+
+    // The cut off at the limit may fall inside a character, so the size goes first
+    check_source_size(bytes.len(), options.max_source_bytes);
+    let mut source = String::from_utf8(bytes).expect("Program is not valid UTF-8");
+
+    if options.macros {
+        // Macros can make the program far longer than the file, so expansion stops at the limit
+        source = match expand_macros_with_limit(&source, options.max_source_bytes.unwrap_or(usize::MAX)) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{}", error);
+                exit(1);
+            },
+        };
     }
-    
-    let filename = &args[1];
 
-    // Read file
-    let mut file = File::open(filename).expect("Executable file not found");
-    let mut source = String::new();
-    file.read_to_string(&mut source).expect("Failed to read executable file");
+    if options.emit_script {
+        print!("{}", shell_script(&source, &options));
+        return;
+    }
+
+    // How long each phase took, for --time-phases
+    let mut phases: Vec<(&str, f64)> = Vec::new();
+    let mut phase_started = Instant::now();
+
+    // Strict programs have no room for comments of any kind
+    let mut opcodes = if options.strict_chars {
+        match lex_strict(source, options.lex_options()) {
+            Ok(opcodes) => opcodes,
+            Err(error) => {
+                eprintln!("{}", error);
+                exit(1);
+            },
+        }
+    } else {
+        lex(source, options.lex_options())
+    };
+    phases.push(("lex", phase_started.elapsed().as_secs_f64()));
+
+    if options.repair {
+        let (repaired, repairs) = repair(opcodes);
+        opcodes = repaired;
+
+        for problem in repairs {
+            match problem {
+                Repair::DroppedLoopEnd(i) => eprintln!("Warning: dropped loop end at #{} that has no beginning", i),
+                Repair::ClosedLoopBegin(i) => eprintln!("Warning: closed loop that starts at #{} at the end of the program", i),
+            }
+        }
+    }
+
+    phase_started = Instant::now();
+    let parsed = match options.max_depth {
+        Some(max_depth) => parse_with_max_depth(opcodes, max_depth),
+        None => parse(opcodes),
+    };
+
+    let mut program = match parsed {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        },
+    };
+
+    phases.push(("parse", phase_started.elapsed().as_secs_f64()));
+
+    // Runs before optimizing, so the positions match the source
+    if options.lint {
+        // In a band, the offsets the lints follow may wrap around to cells that were written
+        if options.band.is_none() {
+            for (position, offset) in unwritten_reads(&program) {
+                eprintln!("Warning: cell at offset {} is read at #{} before anything is written to it", offset, position);
+            }
+        }
+
+        // Seeded or restored memory may have any value in the guards
+        if options.seed_from_file.is_none() && options.resume.is_none() && options.band.is_none() {
+            for position in dead_loops(&program) {
+                eprintln!("Warning: loop at #{} is never entered, its guard is always zero there", position);
+            }
+        }
+
+        // With no input file and nothing piped in, reads wait on the terminal, unless
+        // --strict-eof says what the end of the input means
+        if options.inputs.is_empty() && std::io::stdin().is_terminal() && !options.strict_eof && reads_input(&flatten(&program)) {
+            eprintln!("Warning: program reads input, but no input file is given and nothing is piped in");
+        }
+    }
+
+    if options.loop_report {
+        loop_report(&program);
+        return;
+    }
+
+    if options.verify_opts {
+        let mut input = Vec::new();
+        open_inputs(&options).read_to_end(&mut input).expect("Failed to read input");
+        verify_passes(&program, &input, &options);
+        return;
+    }
+
+    if options.optimize && (options.opt_report || options.time_phases) {
+        for (name, pass) in PASSES {
+            phase_started = Instant::now();
+            let before = instruction_count(&program);
+            program = pass(program);
+            let after = instruction_count(&program);
+            phases.push((name, phase_started.elapsed().as_secs_f64()));
+
+            if options.opt_report {
+                let reduction = if before == 0 { 0.0 } else { (before - after) as f64 * 100.0 / before as f64 };
+                eprintln!("{}: {} -> {} instructions ({:.1}% fewer)", name, before, after, reduction);
+            }
+        }
+    } else if options.optimize {
+        program = optimize(program);
+    }
+
+    if options.optimize && options.fold_output && can_fold_output(&options) {
+        phase_started = Instant::now();
+        let (memory_size, origin) = if options.bidirectional_tape { (2048, 1024_usize) } else { (1024, 0) };
+        let start = origin.saturating_add_signed(options.start_pointer.unwrap_or(0)).min(memory_size);
+        program = fold_output(program, memory_size - start);
+        phases.push(("fold output", phase_started.elapsed().as_secs_f64()));
+    }
+
+    if options.print_size {
+        println!("{}", instruction_count(&program));
+        return;
+    }
+
+    if options.emit_ast_json {
+        println!("{}", to_json(&program));
+        return;
+    }
+
+    if options.explain {
+        explain(&program, 0);
+        return;
+    }
+
+    let (ops, positions) = flatten_with_positions(&program);
+
+    if options.disasm {
+        disasm(&ops);
+        return;
+    }
+
+    if options.truth_table {
+        truth_table(&ops, &options);
+        return;
+    }
+
+    if let Some(path) = &options.equiv {
+        let other_ops = compile_file(path, &options);
+        let programs = [(options.filename.as_str(), &ops[..]), (path.as_str(), &other_ops[..])];
+        check_equivalence(programs, &options);
+        return;
+    }
+
+    match options.tape.as_str() {
+        "dense" => (),
+        "sparse" => {
+            reject_unsupported("--tape sparse", &[
+                ("--checkpoint", options.checkpoint.is_some()),
+                ("--resume", options.resume.is_some()),
+                ("--trace", options.trace),
+                ("--watch", options.watch.is_some()),
+                ("--start-pointer", options.start_pointer.is_some()),
+                ("--seed-from-file", options.seed_from_file.is_some()),
+                ("--bidirectional-tape", options.bidirectional_tape),
+                ("--band", options.band.is_some()),
+                ("--soft-limit", options.soft_limit.is_some()),
+                ("--predict-output", options.predict_output),
+                ("--tape-diff", options.tape_diff),
+                ("--tape-coverage", options.tape_coverage),
+                ("--output-hash", options.output_hash),
+                ("--emit-trace", options.emit_trace.is_some()),
+                ("--guard-cell", options.guard_cell.is_some()),
+                ("--log-cell", options.log_cell.is_some()),
+                ("--asan", options.asan),
+                ("--raw-tty", options.raw_tty),
+                ("--frames", options.frames.is_some()),
+                ("--repeat", options.repeat.is_some()),
+                ("--time-phases", options.time_phases),
+            ]);
+
+            run_sparse(&ops, &positions, &options);
+            return;
+        },
+        _ => usage(),
+    }
+
+    if let Some(runs) = options.repeat {
+        if runs == 0 {
+            usage();
+        }
+
+        // These look at the machine while it runs or once it is done, which only a single run does
+        reject_unsupported("--repeat", &[
+            ("--checkpoint", options.checkpoint.is_some()),
+            ("--trace", options.trace),
+            ("--watch", options.watch.is_some()),
+            ("--show-pointer", options.show_pointer),
+            ("--report-peak", options.report_peak),
+            ("--soft-limit", options.soft_limit.is_some()),
+            ("--predict-output", options.predict_output),
+            ("--tape-diff", options.tape_diff),
+            ("--tape-coverage", options.tape_coverage),
+            ("--output-hash", options.output_hash),
+            ("--emit-trace", options.emit_trace.is_some()),
+            ("--guard-cell", options.guard_cell.is_some()),
+            ("--log-cell", options.log_cell.is_some()),
+            ("--asan", options.asan),
+            ("--raw-tty", options.raw_tty),
+            ("--frames", options.frames.is_some()),
+            ("--time-phases", options.time_phases),
+        ]);
+
+        repeat_runs(&ops, &positions, &options, runs);
+        return;
+    }
+
+    let mut machine = setup_machine(&options);
+
+    // Indices of the cells the options name, which setup_machine made sure are in the memory
+    let watched = options.watch.and_then(|number| machine.cell_index(number));
+    let guarded = options.guard_cell.and_then(|number| machine.cell_index(number));
+    let logged = options.log_cell.and_then(|number| machine.cell_index(number));
+
+    // Predicted output is collected in memory, with no input at all
+    let mut predicted = Vec::new();
+    let mut output_hash = Fnv1a::new();
+
+    // Input files are read one after another as a single stream, and not even opened for a
+    // program that never reads
+    let reads = reads_input(&ops);
+    let input: Box<dyn Read> = if options.predict_output || !reads {
+        Box::new(std::io::empty())
+    } else {
+        open_inputs(&options)
+    };
+
+    // Raw mode only makes sense for keys typed right into the program
+    let input = if options.raw_tty && options.inputs.is_empty() && std::io::stdin().is_terminal() {
+        enter_raw_tty(input)
+    } else {
+        input
+    };
 
-    let opcodes = lex(source);
+    let output: Box<dyn Write> = if options.predict_output {
+        Box::new(&mut predicted)
+    } else if options.output_hash {
+        Box::new(&mut output_hash)
+    } else {
+        open_output(&options)
+    };
 
-    let program = parse(opcodes);
+    let output = wrap_output(output, &options);
 
-    let mut bf_memory: Vec<u8> = vec![0; 1024];
-    let mut data_pointer = 0;
+    let mut io = program_io(input, output, &options);
 
-    run(&program, &mut bf_memory, &mut data_pointer);
-}
\ No newline at end of file
+    let initial_tapes = if options.tape_diff {
+        let (first, second) = machine.tapes();
+        (first.to_vec(), second.to_vec())
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    // Optimized programs change cells in a different order, but never across input or output,
+    // so the cells are only compared right before those and at the end
+    let mut write_log = options.emit_trace.as_ref().map(|path| {
+        let file = File::create(path).expect("Failed to create trace file");
+        let other_tape = if machine.other_tape.is_empty() { vec![0; machine.bf_memory.len()] } else { machine.other_tape.clone() };
+        (std::io::BufWriter::new(file), machine.bf_memory.clone(), other_tape)
+    });
+
+    let mut visited = vec![false; if options.tape_coverage { machine.bf_memory.len() } else { 0 }];
+
+    // Every value the logged cell has taken, starting with the one it has now
+    let mut cell_log = Vec::new();
+    if let Some(index) = logged {
+        cell_log.push(machine.bf_memory[index]);
+    }
+
+    // Tracing, watching, coverage, the logs and the sanitizer need to look at the machine after
+    // every single operation
+    let single_step = options.trace
+        || options.watch.is_some()
+        || options.tape_coverage
+        || write_log.is_some()
+        || options.log_cell.is_some()
+        || options.asan;
+
+    let mut next_checkpoint = options.checkpoint_interval;
+    let mut next_frame = options.frames.map(|every| machine.steps + every);
+    let mut frame_number = 0;
+    if options.frames.is_some() {
+        std::fs::create_dir_all(&options.frames_dir).expect("Failed to create frames directory");
+    }
+
+    let endless = if options.sandbox { endless_loops(&ops) } else { Vec::new() };
+    let started = Instant::now();
+
+    loop {
+        let mut steps = if single_step { 1 } else { next_checkpoint - machine.steps };
+
+        if let Some(max_steps) = options.max_steps {
+            if machine.steps >= max_steps {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("Step limit of {} reached", max_steps);
+                exit(1);
+            }
+
+            steps = steps.min(max_steps - machine.steps);
+        }
+
+        if let Some(next_frame) = next_frame {
+            steps = steps.min(next_frame - machine.steps);
+        }
+
+        // The first operation may still set up the guarded cell
+        if guarded.is_some() && machine.guard_cell.is_none() {
+            if machine.steps == 0 && !options.guard_from_start {
+                steps = 1;
+            } else {
+                machine.guard_cell = guarded;
+            }
+        }
+
+        let program_counter = machine.program_counter;
+        let position = positions.get(program_counter).copied().unwrap_or(program_counter);
+
+        if options.trace {
+            if let Some(op) = ops.get(program_counter) {
+                eprintln!("#{} {:?}  {}", program_counter, op, machine.render_window(options.window));
+            }
+        }
+
+        if options.asan {
+            let offsets = match ops.get(program_counter) {
+                Some(Op::IncrementPointer | Op::Duplicate) => 1..=1,
+                Some(Op::DecrementPointer) => -1..=-1,
+                Some(Op::Move(offset) | Op::MultiplyAdd { offset, .. }) => *offset..=*offset,
+                Some(Op::ClearRange { start_offset, count }) => *start_offset..=start_offset + *count as isize - 1,
+                _ => 0..=0,
+            };
+
+            // Within the band, its edges are the ones that matter, whatever happens past them
+            let (low, high) = match machine.band {
+                Some((low, high)) if (low..=high).contains(&machine.data_pointer) => (low, high),
+                _ => (0, machine.bf_memory.len() - 1),
+            };
+
+            for offset in offsets {
+                let cell = machine.data_pointer as isize + offset;
+
+                if cell < low as isize || cell > high as isize {
+                    let origin = machine.origin as isize;
+                    eprintln!("Warning: access to cell {} at #{} is outside of cells {}..={}", cell - origin, position, machine.cell_number(low), machine.cell_number(high));
+                }
+            }
+        }
+
+        if options.tape_coverage {
+            match ops.get(program_counter) {
+                None | Some(Op::IncrementPointer | Op::DecrementPointer | Op::Move(_)) => (),
+                Some(Op::MultiplyAdd { offset, .. }) => {
+                    visited[machine.data_pointer] = true;
+
+                    if let Some(target) = machine.cell_at_offset(*offset) {
+                        visited[target] = true;
+                    }
+                },
+                Some(Op::Duplicate) => {
+                    visited[machine.data_pointer] = true;
+
+                    if let Some(target) = machine.cell_at_offset(1) {
+                        visited[target] = true;
+                    }
+                },
+                Some(Op::ClearRange { start_offset, count }) => {
+                    for offset in *start_offset..start_offset + *count as isize {
+                        if let Some(target) = machine.cell_at_offset(offset) {
+                            visited[target] = true;
+                        }
+                    }
+                },
+                Some(_) => visited[machine.data_pointer] = true,
+            }
+        }
+
+        if let Some((log, snapshot, other_snapshot)) = &mut write_log {
+            match ops.get(program_counter) {
+                Some(Op::Write | Op::Emit(_) | Op::Read) => log_writes(log, snapshot, &machine),
+                Some(Op::SwitchTape) => {
+                    log_writes(log, snapshot, &machine);
+                    writeln!(log, "$").expect("Failed to write trace");
+                    std::mem::swap(snapshot, other_snapshot);
+                },
+                _ => (),
+            }
+        }
+
+        // Switching tapes changes every cell at once, which is not worth reporting
+        let watched_before = match ops.get(program_counter) {
+            Some(Op::SwitchTape) => None,
+            _ => watched.map(|index| machine.bf_memory[index]),
+        };
+
+        let ran = machine.run_steps_reading(&ops, steps, &mut io, reads);
+        let running = match ran {
+            Ok(running) => running,
+            Err(error) => {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("{}", error.at_source(&positions));
+                exit(1);
+            },
+        };
+
+        // A program stuck in one of these would only be stopped by the limits much later
+        let stuck = endless.iter().find(|(begin, end)| (begin + 1..=*end).contains(&machine.program_counter));
+        if let Some((begin, _)) = stuck {
+            io.output.flush().expect("Failed to flush output");
+            eprintln!("Loop at #{} never ends, its body only moves the pointer back to the same cell", positions[*begin]);
+            exit(1);
+        }
+
+        if let (Some(index), Some(before)) = (watched, watched_before) {
+            let after = machine.bf_memory[index];
+
+            if after != before {
+                eprintln!("Watched cell {} changed from {} to {} at #{}", machine.cell_number(index), before, after, position);
+                pause_at_watch(&mut io);
+            }
+        }
+
+        if let Some(index) = logged {
+            let value = machine.bf_memory[index];
+
+            if !matches!(ops.get(program_counter), Some(Op::SwitchTape)) && cell_log.last() != Some(&value) {
+                cell_log.push(value);
+            }
+        }
+
+        if machine.peak_pointer > machine.pointer_limit {
+            if options.soft_limit_strict {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("Error: data pointer moved past the soft limit of {} to cell {}", machine.cell_number(machine.pointer_limit), machine.cell_number(machine.peak_pointer));
+                exit(1);
+            }
+
+            eprintln!("Warning: data pointer moved past the soft limit of {} to cell {}", machine.cell_number(machine.pointer_limit), machine.cell_number(machine.peak_pointer));
+
+            // Only warn the first time the limit is crossed
+            machine.pointer_limit = usize::MAX;
+        }
+
+        if let (Some(every), Some(frame_step)) = (options.frames, next_frame) {
+            if machine.steps == frame_step {
+                next_frame = Some(frame_step + every);
+                frame_number += 1;
+
+                let path = format!("{}/{:06}.txt", options.frames_dir, frame_number);
+                std::fs::write(path, render_frame(&machine)).expect("Failed to write frame");
+            }
+        }
+
+        if !running {
+            break;
+        }
+
+        if let Some(timeout) = options.timeout {
+            if started.elapsed().as_secs_f64() > timeout {
+                io.output.flush().expect("Failed to flush output");
+                eprintln!("Time limit of {} seconds reached", timeout);
+                exit(1);
+            }
+        }
+
+        if machine.steps == next_checkpoint {
+            next_checkpoint += options.checkpoint_interval;
+
+            if let Some(path) = &options.checkpoint {
+                // Everything written so far belongs to the state being saved
+                io.output.flush().expect("Failed to flush output");
+                machine.save(path).expect("Failed to write checkpoint");
+            }
+        }
+    }
+
+    io.output.flush().expect("Failed to flush output");
+    drop(io);
+    restore_tty();
+
+    if let Some((mut log, mut snapshot, _)) = write_log {
+        log_writes(&mut log, &mut snapshot, &machine);
+        log.flush().expect("Failed to write trace");
+    }
+    phases.push(("run", started.elapsed().as_secs_f64()));
+
+    if options.predict_output {
+        println!("\"{}\"", predicted.escape_ascii());
+    }
+
+    if options.output_hash {
+        println!("{:016x}", output_hash.0);
+    }
+
+    if options.show_pointer {
+        eprintln!("Data pointer: {}", machine.cell_number(machine.data_pointer));
+    }
+
+    if options.report_peak {
+        eprintln!("Peak data pointer: {}", machine.cell_number(machine.peak_pointer));
+    }
+
+    if let Some(index) = logged {
+        let values: Vec<String> = cell_log.iter().map(|value| value.to_string()).collect();
+        eprintln!("Cell {}: {}", machine.cell_number(index), values.join(", "));
+    }
+
+    if options.tape_coverage {
+        let cells: Vec<usize> = visited.iter().enumerate().filter(|(_, visited)| **visited).map(|(i, _)| i).collect();
+
+        match (cells.first(), cells.last()) {
+            (Some(first), Some(last)) => {
+                eprintln!("Cells visited: {} ({}..={})", cells.len(), machine.cell_number(*first), machine.cell_number(*last));
+            },
+            _ => eprintln!("Cells visited: 0"),
+        }
+    }
+
+    if options.tape_diff {
+        let (first, second) = machine.tapes();
+
+        for (i, (old, new)) in initial_tapes.0.iter().zip(first).enumerate() {
+            if old != new {
+                eprintln!("{}: {} -> {}", machine.cell_number(i), old, new);
+            }
+        }
+
+        // Cells of the second tape are told apart by a leading B
+        let untouched = vec![0; second.len()];
+        let initial_second = if initial_tapes.1.is_empty() { &untouched } else { &initial_tapes.1 };
+
+        for (i, (old, new)) in initial_second.iter().zip(second).enumerate() {
+            if old != new {
+                eprintln!("B {}: {} -> {}", machine.cell_number(i), old, new);
+            }
+        }
+    }
+
+    if options.time_phases {
+        for (name, seconds) in phases {
+            eprintln!("{}: {:.3} ms", name, seconds * 1000.0);
+        }
+    }
+}
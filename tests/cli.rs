@@ -0,0 +1,731 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Writes a file for a test to the scratch directory and returns its path
+fn scratch_file(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+    let path = scratch_path(name);
+    std::fs::write(&path, contents).expect("Failed to write scratch file");
+    path
+}
+
+/// Path in the scratch directory, for files the interpreter writes
+fn scratch_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+/// Runs the interpreter with the arguments, and the input on standard input
+fn bfi_rs(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfi_rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start bfi_rs");
+
+    // Programs that never read may have exited already
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().expect("Failed to wait for bfi_rs")
+}
+
+/// Runs a program, written to a scratch file of the given name, with extra arguments before it
+fn run(name: &str, source: &str, args: &[&str], input: &[u8]) -> Output {
+    let path = scratch_file(&format!("{}.bf", name), source);
+    let mut args = args.to_vec();
+    args.push(path.to_str().unwrap());
+    bfi_rs(&args, input)
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Writes A to J, one letter every few steps
+const ALPHABET: &str = "++++++++[>++++++++<-]>+.+.+.+.+.+.+.+.+.+.";
+
+const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+#[test]
+fn resuming_a_checkpoint_finishes_the_output() {
+    let full = run("checkpoint_full", ALPHABET, &[], b"");
+    assert_eq!(full.stdout, b"ABCDEFGHIJ");
+
+    let checkpoint = scratch_path("checkpoint.state");
+    let checkpoint = checkpoint.to_str().unwrap();
+    let first = run("checkpoint_first", ALPHABET, &["--checkpoint", checkpoint, "--checkpoint-interval", "120", "--max-steps", "120"], b"");
+    assert!(!first.status.success());
+
+    let rest = run("checkpoint_rest", ALPHABET, &["--resume", checkpoint], b"");
+    assert!(rest.status.success());
+    assert!(!first.stdout.is_empty() && !rest.stdout.is_empty());
+    assert_eq!([first.stdout, rest.stdout].concat(), full.stdout);
+}
+
+#[test]
+fn input_files_are_read_one_after_another() {
+    let first = scratch_file("input_first.txt", "ab");
+    let second = scratch_file("input_second.txt", "cd");
+    let args = ["--input", first.to_str().unwrap(), "--input", second.to_str().unwrap()];
+
+    let output = run("two_inputs", ",.,.,.,.", &args, b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abcd");
+}
+
+#[test]
+fn explain_indents_loop_bodies() {
+    let output = run("explain_clear", "[-]", &["--explain"], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[  begin loop while current cell nonzero\n  -  decrement current cell\n]  end loop\n");
+}
+
+#[test]
+fn watch_reports_every_change() {
+    let output = run("watch_twice", "++", &["--watch", "0"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Watched cell 0 changed from 0 to 1 at #0\nWatched cell 0 changed from 1 to 2 at #1\n");
+}
+
+#[test]
+fn watch_outside_of_the_memory_is_rejected() {
+    let output = run("watch_outside", "++", &["--watch", "5000"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("outside of the 1024 memory cells"));
+}
+
+#[test]
+fn output_file_gets_the_output() {
+    let path = scratch_path("output_file.out");
+    let output = run("output_file", "+++.", &["--output-file", path.to_str().unwrap()], b"");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read(&path).unwrap(), [3]);
+}
+
+#[test]
+fn show_pointer_reports_the_final_cell() {
+    let output = run("show_pointer", ">>>", &["--show-pointer"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Data pointer: 3\n");
+}
+
+#[test]
+fn start_pointer_moves_the_first_cell() {
+    let output = run("start_pointer", "+", &["--start-pointer", "5", "--tape-diff"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "5: 0 -> 1\n");
+}
+
+#[test]
+fn echo_input_writes_every_byte_read() {
+    let output = run("echo_input", ",.", &["--echo-input"], b"A");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AA");
+}
+
+#[test]
+fn sandbox_stops_endless_loops() {
+    let output = run("sandbox_endless", "+[]", &["--sandbox"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("never ends"));
+
+    let output = run("sandbox_busy", "+[-+]", &["--sandbox"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Step limit of 100000000 reached\n");
+}
+
+#[test]
+fn sandbox_rejects_extensions() {
+    for extension in [&["--ext-dual-tape"][..], &["--ext-dup"], &["--tape", "sparse"]] {
+        let output = run("sandbox_extension", "+.", &[&["--sandbox"], extension].concat(), b"");
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn report_peak_gives_the_highest_cell() {
+    let output = run("report_peak", ">>>>>>><<<<<<<", &["--report-peak"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Peak data pointer: 7\n");
+}
+
+#[test]
+fn strict_soft_limit_is_an_error() {
+    let output = run("soft_limit_strict", ">>>>>>>>>>>+.", &["--soft-limit", "10", "--soft-limit-strict"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Error: data pointer moved past the soft limit of 10 to cell 11\n");
+
+    let output = run("soft_limit", ">>>>>>>>>>>+.", &["--soft-limit", "10"], b"");
+    assert!(output.status.success());
+    assert!(stderr(&output).starts_with("Warning"));
+}
+
+#[test]
+fn predict_output_prints_the_greeting() {
+    let output = run("predict_output", HELLO_WORLD, &["--predict-output"], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "\"Hello World!\\n\"\n");
+}
+
+#[test]
+fn tape_diff_lists_the_changed_cells() {
+    let output = run("tape_diff", "+>>++", &["--tape-diff"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "0: 0 -> 1\n2: 0 -> 2\n");
+}
+
+#[test]
+fn band_wraps_the_pointer_around() {
+    let output = run("band_wrap", ">>>>+", &["--band", "0:3", "--show-pointer", "--tape-diff"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Data pointer: 0\n0: 0 -> 1\n");
+}
+
+#[test]
+fn opt_report_shows_each_pass() {
+    let output = run("opt_report", "+++++[-]", &["-O", "--opt-report", "--explain"], b"");
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("fold runs: 6 -> 2 instructions (66.7% fewer)\n"));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("+  add 5 to current cell\n"));
+}
+
+#[test]
+fn seed_file_fills_the_first_cells() {
+    let seed = scratch_file("seed.bin", "ABC");
+    let output = run("seed", ".>.>.", &["--seed-from-file", seed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ABC");
+}
+
+#[test]
+fn tape_coverage_counts_the_cells_accessed() {
+    let output = run("tape_coverage", "+>+>>>>+<<<<<", &["--tape-coverage"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Cells visited: 3 (0..=5)\n");
+}
+
+#[test]
+fn output_hash_is_stable() {
+    // FNV-1a of "Hello World!\n"
+    let output = run("output_hash", HELLO_WORLD, &["--output-hash"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"9b8d74ce8e354928\n");
+}
+
+#[test]
+fn time_phases_lists_every_phase() {
+    let output = run("time_phases", HELLO_WORLD, &["-O", "--time-phases"], b"");
+    assert!(output.status.success());
+
+    let labels: Vec<String> = stderr(&output).lines().map(|line| line.split(':').next().unwrap().to_string()).collect();
+    assert_eq!(labels, ["lex", "parse", "lower loops", "fold runs", "remove dead code", "fuse clears", "run"]);
+}
+
+#[test]
+fn repeat_times_every_run() {
+    let output = run("repeat", "+++.", &["--repeat", "3"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3, 3, 3]);
+
+    let lines: Vec<String> = stderr(&output).lines().map(|line| line.split(':').next().unwrap().to_string()).collect();
+    assert_eq!(lines, ["Run 1", "Run 2", "Run 3", "Mean"]);
+}
+
+#[test]
+fn repeat_sets_up_every_run_like_a_single_one() {
+    let seed = scratch_file("repeat_seed.bin", [0, 0, 7]);
+    let path = scratch_path("repeat_output.out");
+    let args = ["--repeat", "2", "--seed-from-file", seed.to_str().unwrap(), "--start-pointer", "2", "--output-file", path.to_str().unwrap()];
+
+    let output = run("repeat_setup", "+.", &args, b"");
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&path).unwrap(), [8, 8]);
+
+    let output = run("repeat_limit", "+[]", &["--repeat", "2", "--max-steps", "100"], b"");
+    assert_eq!(stderr(&output), "Step limit of 100 reached\n");
+
+    let output = run("repeat_trace", "+.", &["--repeat", "2", "--trace"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "--trace can't be used with --repeat\n");
+}
+
+#[test]
+fn emitted_script_runs_the_program_on_standard_input() {
+    let output = run("emit_script", ",.", &["--emit-script"], b"");
+    assert!(output.status.success());
+
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("\n,.\n"));
+    assert!(script.contains("exec bfi_rs \"$@\" /dev/fd/3"));
+
+    // The script leaves standard input to the program
+    assert_eq!(run_script("emit_script.sh", &script, b"x"), b"x");
+}
+
+/// Runs a script made by --emit-script with the input on standard input, returning what it
+/// wrote, with the interpreter on the path the way the script looks for it
+fn run_script(name: &str, script: &str, input: &[u8]) -> Vec<u8> {
+    let path = scratch_file(name, script);
+    let binaries = std::path::Path::new(env!("CARGO_BIN_EXE_bfi_rs")).parent().unwrap();
+    let search_path = format!("{}:{}", binaries.display(), std::env::var("PATH").unwrap_or_default());
+
+    let mut child = Command::new("sh")
+        .arg(&path)
+        .env("PATH", search_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start sh");
+    // Programs that never read may have exited already
+    let _ = child.stdin.take().unwrap().write_all(input);
+
+    child.wait_with_output().unwrap().stdout
+}
+
+#[test]
+fn emitted_script_keeps_the_dialect() {
+    let source = "++++++++[>++++++++<-]>+. ; writes A, and nothing else.\n";
+    let output = run("emit_script_dialect", source, &["--emit-script", "-O", "--comment-delim", ";"], b"");
+    assert!(output.status.success());
+
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("exec bfi_rs -O --comment-delim ';' \"$@\" /dev/fd/3"), "{}", script);
+    assert_eq!(run_script("emit_script_dialect.sh", &script, b"x"), b"A");
+}
+
+#[test]
+fn strict_eof_stops_at_a_read_past_the_end() {
+    let output = run("strict_eof", ",", &["--strict-eof"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Read past the end of the input at #0\n");
+
+    let output = run("lenient_eof", "+,.", &[], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+}
+
+#[test]
+fn optimized_errors_report_the_same_position() {
+    let plain = run("error_position", "+[->+<]>,,<<<", &["--strict-eof"], b"a");
+    let optimized = run("error_position_optimized", "+[->+<]>,,<<<", &["-O", "--strict-eof"], b"a");
+
+    assert_eq!(stderr(&plain), "Read past the end of the input at #9\n");
+    assert_eq!(stderr(&optimized), stderr(&plain));
+}
+
+#[test]
+fn optimizing_keeps_the_write_trace() {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let source = fixtures.join("calculator.bf");
+    let input = std::fs::read(fixtures.join("calculator.in")).unwrap();
+
+    let plain = scratch_path("trace_plain.log");
+    let optimized = scratch_path("trace_optimized.log");
+    bfi_rs(&["--emit-trace", plain.to_str().unwrap(), source.to_str().unwrap()], &input);
+    bfi_rs(&["-O", "--emit-trace", optimized.to_str().unwrap(), source.to_str().unwrap()], &input);
+
+    let trace = std::fs::read(&plain).unwrap();
+    assert!(!trace.is_empty());
+    assert_eq!(std::fs::read(&optimized).unwrap(), trace);
+}
+
+#[test]
+fn dual_tape_dump_shows_both_tapes() {
+    let output = run("dual_tape", "+++>+$++.", &["--ext-dual-tape", "--tape-diff"], b"");
+    assert_eq!(output.stdout, [2]);
+    assert_eq!(stderr(&output), "0: 0 -> 3\n1: 0 -> 1\nB 0: 0 -> 2\n");
+
+    // Without the flag, `$` is a comment and everything lands on the one tape
+    let output = run("single_tape", "+++>+$++.", &["--tape-diff"], b"");
+    assert_eq!(stderr(&output), "0: 0 -> 3\n1: 0 -> 3\n");
+}
+
+#[test]
+fn gzipped_programs_are_decompressed() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(HELLO_WORLD.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let path = scratch_file("hello_world.bf.gz", &compressed);
+    assert_eq!(bfi_rs(&[path.to_str().unwrap()], b"").stdout, b"Hello World!\n");
+    assert_eq!(bfi_rs(&["--gzip", "-"], &compressed).stdout, b"Hello World!\n");
+}
+
+#[test]
+fn loop_report_names_the_idioms() {
+    let output = run("loop_report", "[-][->+<][>]", &["--loop-report"], b"");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "#0 clear: balanced, changes guard\n#3 copy: balanced, changes guard\n#9 scan: moves 1, keeps guard\n");
+}
+
+#[test]
+fn pointer_only_loops_are_skipped_or_stopped() {
+    // The loop on a zero cell takes a single step
+    let output = run("skipped_scan", "[>]+.", &["--max-steps", "3"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+
+    let output = run("endless_scan", "+[><]", &["--sandbox"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Loop at #1 never ends, its body only moves the pointer back to the same cell\n");
+}
+
+#[test]
+fn nesting_past_the_max_depth_is_rejected() {
+    let output = run("max_depth", "[[[", &["--max-depth", "2"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Loops are nested 3 levels deep, the limit is 2\n");
+}
+
+#[test]
+fn output_map_translates_bytes() {
+    let mut map: Vec<u8> = (0..=255).collect();
+    map[0] = b'#';
+    let map = scratch_file("output.map", map);
+
+    let output = run("output_map", ".+.", &["--output-map", map.to_str().unwrap()], b"");
+    assert_eq!(output.stdout, [b'#', 1]);
+}
+
+#[test]
+fn equiv_finds_a_counterexample() {
+    let echo = scratch_file("equiv_echo.bf", ",.");
+    let echo = echo.to_str().unwrap();
+
+    let other = scratch_file("equiv_same.bf", ",>[-]<.");
+    let output = run("equiv_echo_same", ",.", &["--equiv", other.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Programs wrote the same output for all 100 inputs\n");
+
+    let other = scratch_file("equiv_increment.bf", ",+.");
+    let output = bfi_rs(&["--equiv", other.to_str().unwrap(), "--equiv-inputs", "5", echo], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Programs differ for input \"\":\n"));
+}
+
+#[test]
+fn log_cell_lists_every_value() {
+    let output = run("log_cell", "+++", &["--log-cell", "0"], b"");
+    assert_eq!(stderr(&output), "Cell 0: 0, 1, 2, 3\n");
+}
+
+#[test]
+fn programs_without_reads_write_the_same_output() {
+    let output = run("no_input", "++.", &[], b"ignored");
+    assert_eq!(output.stdout, [2]);
+
+    let output = run("with_input", ",+.", &[], b"a");
+    assert_eq!(output.stdout, b"b");
+}
+
+#[test]
+fn disasm_lists_the_jump_targets() {
+    let output = run("disasm", "+++[-]", &["--disasm"], b"");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0000  inc\n0001  inc\n0002  inc\n0003  jz         0006\n0004  dec\n0005  jnz        0004\n");
+}
+
+#[test]
+fn asan_warns_past_the_nominal_size() {
+    // The band wraps the pointer around, and the warning still names the access
+    let output = run("asan_band", ">>>>+.", &["--asan", "--band", "0:3"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+    assert_eq!(stderr(&output), "Warning: access to cell 4 at #3 is outside of cells 0..=3\n");
+
+    let output = run("asan_end", ">", &["--asan", "--start-pointer", "1023"], b"");
+    assert_eq!(stderr(&output), "Warning: access to cell 1024 at #0 is outside of cells 0..=1023\nData pointer moved right of the last cell at #0\n");
+}
+
+#[test]
+fn sparse_tape_reaches_far_cells() {
+    let source = format!("{}++.", ">".repeat(1_000_000));
+    let output = run("sparse_far", &source, &["--tape", "sparse", "--show-pointer"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [2]);
+    assert_eq!(stderr(&output), "Data pointer: 1000000\n");
+
+    // Options that need a dense memory are refused rather than ignored
+    let output = run("sparse_watch", "+.", &["--tape", "sparse", "--watch", "0"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "--watch can't be used with --tape sparse\n");
+}
+
+#[test]
+fn bidirectional_cells_are_numbered_from_the_start() {
+    let output = run("bidirectional_watch", "<+", &["--bidirectional-tape", "--tape-diff", "--watch", "-1", "--log-cell", "-1"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Watched cell -1 changed from 0 to 1 at #1\nCell -1: 0, 1\n-1: 0 -> 1\n");
+
+    // The seed starts on cell 0 too
+    let seed = scratch_file("bidirectional.seed", "AB");
+    let output = run("bidirectional_seed", ".>.<<+", &["--bidirectional-tape", "--seed-from-file", seed.to_str().unwrap(), "--start-pointer", "-1"], b"");
+    assert_eq!(output.stdout, b"\0A");
+
+    let output = run("bidirectional_outside", "<+", &["--bidirectional-tape", "--guard-cell", "-1025"], b"");
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Guard cell -1025 is outside of the 2048 memory cells\n");
+}
+
+#[test]
+fn lint_flags_loops_on_untouched_cells() {
+    let output = run("dead_loop", "[+]+.[-]", &["--lint"], b"");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "Warning: cell at offset 0 is read at #0 before anything is written to it\nWarning: loop at #0 is never entered, its guard is always zero there\n");
+}
+
+#[test]
+fn prompt_comes_before_every_read() {
+    let output = run("prompt", ",.,.", &["--prompt", "? "], b"ab");
+    assert_eq!(output.stdout, b"? a? b");
+}
+
+#[test]
+fn truth_table_of_echo_is_the_identity() {
+    let output = run("truth_table", ",.", &["--truth-table"], b"");
+    let expected: String = (0..=u8::MAX).map(|byte| format!("{:3} -> \"{}\"\n", byte, [byte].escape_ascii())).collect();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // A second read finds the input at its end
+    let output = run("truth_table_twice", ",.,.", &["--truth-table"], b"");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("  0 -> \"\\x00\\x00\"\n  1 -> \"\\x01\\x01\"\n"));
+}
+
+#[test]
+fn print_size_counts_after_optimizing() {
+    assert_eq!(run("print_size", "+++", &["--print-size"], b"").stdout, b"3\n");
+    assert_eq!(run("print_size_optimized", "+++", &["-O", "--print-size"], b"").stdout, b"1\n");
+}
+
+#[test]
+fn folding_keeps_what_the_reports_see() {
+    let plain = run("fold_peak", HELLO_WORLD, &["-O", "--report-peak", "--log-cell", "1"], b"");
+    let folded = run("fold_peak_folded", HELLO_WORLD, &["-O", "--fold-output", "--report-peak", "--log-cell", "1"], b"");
+
+    assert_eq!(folded.stdout, b"Hello World!\n");
+    assert_eq!(stderr(&folded), stderr(&plain));
+}
+
+#[test]
+fn repeat_collect_prints_every_output() {
+    let output = run("repeat_collect", "+++.", &["--repeat", "2", "--repeat-collect"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3, b'\n', 3]);
+
+    let output = run("repeat_collect_delimiter", "+++.", &["--repeat", "2", "--repeat-collect", "--repeat-delimiter", "--"], b"");
+    assert_eq!(output.stdout, [3, b'-', b'-', 3]);
+}
+
+#[test]
+fn programs_over_the_source_limit_are_refused_before_lexing() {
+    // Lexing with --strict-chars would refuse the letters with a different error
+    let output = run("max_source_bytes", "+++.abc]", &["--max-source-bytes", "4", "--strict-chars"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Program is longer than the limit of 4 bytes\n");
+
+    let output = run("max_source_bytes_fits", "+++.", &["--max-source-bytes", "4"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+}
+
+#[test]
+fn tee_copies_the_output_to_a_file() {
+    let path = scratch_path("tee.out");
+    let output = run("tee", "+++.", &["--tee", path.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+    assert_eq!(std::fs::read(&path).unwrap(), [3]);
+}
+
+#[test]
+fn strict_chars_refuses_stray_letters() {
+    let output = run("strict_chars", "+a+.", &["--strict-chars"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Unexpected character 'a' at line 1, column 2\n");
+
+    let output = run("loose_chars", "+a+.", &[], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [2]);
+}
+
+#[test]
+fn frames_are_written_every_n_steps() {
+    let frame_count = |every: &str| {
+        let directory = scratch_path(&format!("frames_{}", every));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let output = run("frames", "+>+<-", &["--frames", every, "--frames-dir", directory.to_str().unwrap()], b"");
+        assert!(output.status.success());
+        std::fs::read_dir(&directory).unwrap().count()
+    };
+
+    assert_eq!(frame_count("1"), 5);
+    assert_eq!(frame_count("2"), 2);
+
+    let last = std::fs::read_to_string(scratch_path("frames_1/000005.txt")).unwrap();
+    assert!(last.starts_with("Step 5\n     0:[  0]   1 "), "{}", last);
+
+    let output = run("frames_zero", "+>+<-", &["--frames", "0"], b"");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn lint_stays_quiet_when_input_is_piped_in() {
+    // The warning is only for a terminal, and the tests always pipe standard input
+    let output = run("lint_piped", ",.", &["--lint"], b"a");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a");
+    assert_eq!(stderr(&output), "");
+
+    let input = scratch_file("lint_input.in", "a");
+    let output = run("lint_input", ",.", &["--lint", "--input", input.to_str().unwrap()], b"");
+    assert_eq!(stderr(&output), "");
+
+    let output = run("lint_strict_eof", ",.", &["--lint", "--strict-eof"], b"a");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn sandbox_rejects_a_band() {
+    let output = run("sandbox_band", "<+.", &["--sandbox", "--band", "0:3"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "--sandbox does not allow --band\n");
+}
+
+#[test]
+fn frames_escape_bytes_that_are_not_printable() {
+    let directory = scratch_path("frames_escaped");
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let output = run("frames_escaped", "++++++++++>+", &["--frames", "12", "--frames-dir", directory.to_str().unwrap()], b"");
+    assert!(output.status.success());
+
+    let frame = std::fs::read_to_string(directory.join("000001.txt")).unwrap();
+    assert!(frame.contains("  \\n\\x01\\x00\\x00"), "{}", frame);
+    assert!(!frame.bytes().any(|byte| byte < b' ' && byte != b'\n'), "{}", frame);
+}
+
+#[test]
+fn clearing_more_cells_than_the_band_holds_clears_all_of_it() {
+    for optimize in [&[][..], &["-O"]] {
+        let output = run("band_clear_range", "+>+<[-]>[-]>[-]", &[optimize, &["--band", "0:1", "--tape-diff"]].concat(), b"");
+        assert!(output.status.success());
+        assert_eq!(stderr(&output), "", "{:?}", optimize);
+    }
+}
+
+#[test]
+fn repeat_rejects_time_phases() {
+    let output = run("repeat_time_phases", "+.", &["--repeat", "2", "--time-phases"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "--time-phases can't be used with --repeat\n");
+}
+
+#[test]
+fn equiv_needs_at_least_one_input() {
+    let other = scratch_file("equiv_zero_other.bf", ",.");
+    let output = run("equiv_zero", ",.", &["--equiv", other.to_str().unwrap(), "--equiv-inputs", "0"], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: "));
+}
+
+#[test]
+fn emitted_trace_numbers_cells_from_the_start() {
+    let path = scratch_path("bidirectional_trace.txt");
+    let output = run("bidirectional_trace", "<+", &["--bidirectional-tape", "--emit-trace", path.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "-1 1\n");
+}
+
+#[test]
+fn lint_leaves_loops_in_a_band_alone() {
+    let output = run("dead_loop_band", "+>[-.]", &["--lint", "--band", "0:0"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0]);
+    assert_eq!(stderr(&output), "");
+
+    let output = run("dead_loop_no_band", "+>[-.]", &["--lint"], b"");
+    assert!(stderr(&output).contains("Warning: loop at #2 is never entered, its guard is always zero there\n"));
+}
+
+#[test]
+fn folded_output_leaves_checkpoints_and_limits_alone() {
+    let resumed_output = |name: &str, fold: &[&str]| {
+        let checkpoint = scratch_path(&format!("{}.state", name));
+        let checkpoint = checkpoint.to_str().unwrap();
+
+        let first = run(name, ALPHABET, &[&["-O", "--checkpoint", checkpoint, "--checkpoint-interval", "20"], fold].concat(), b"");
+        assert_eq!(first.stdout, b"ABCDEFGHIJ");
+
+        let rest = run(name, ALPHABET, &[&["-O", "--resume", checkpoint], fold].concat(), b"");
+        assert!(rest.status.success());
+        rest.stdout
+    };
+
+    let unfolded = resumed_output("checkpoint_unfolded", &[]);
+    assert!(!unfolded.is_empty());
+    assert_eq!(resumed_output("checkpoint_folded", &["--fold-output"]), unfolded);
+
+    let output = run("fold_step_limit", HELLO_WORLD, &["-O", "--fold-output", "--max-steps", "10"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Step limit of 10 reached\n");
+}
+
+#[test]
+fn truth_table_runs_on_the_memory_the_options_set_up() {
+    let output = run("truth_table_bidirectional", "<,.", &["--bidirectional-tape", "--truth-table"], b"");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\n 65 -> \"A\"\n"));
+}
+
+#[test]
+fn equiv_runs_on_the_memory_the_options_set_up() {
+    let other = scratch_file("equiv_bidirectional_other.bf", ",.");
+    let output = run("equiv_bidirectional", "<,.", &["--bidirectional-tape", "--equiv", other.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Programs wrote the same output for all 100 inputs\n");
+}
+
+#[test]
+fn verify_opts_runs_on_the_memory_the_options_set_up() {
+    let input = scratch_file("verify_bidirectional.in", "a");
+    let output = run("verify_bidirectional", "<,.", &["--bidirectional-tape", "--verify-opts", "--input", input.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("unoptimized: wrote \"a\", finished\n"));
+}
+
+#[test]
+fn macro_expansion_stops_at_the_source_limit() {
+    // Each level doubles the one below, far more than could ever be built in memory
+    let mut source = String::from("@m0 = ++\n");
+    for level in 1..=40 {
+        source.push_str(&format!("@m{} = @m{}@m{}\n", level, level - 1, level - 1));
+    }
+    source.push_str("@m40");
+
+    let output = run("macros_over_the_limit", &source, &["--macros", "--max-source-bytes", "1000"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Program is longer than the limit of 1000 bytes\n");
+
+    let output = run("macros_within_the_limit", "@m0 = ++\n@m1 = @m0@m0\n@m1.", &["--macros", "--max-source-bytes", "1000"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [4]);
+}
+
+#[test]
+fn strict_chars_refuses_comment_options() {
+    for args in [&["--strict-chars", "--comment-delim", "#"][..], &["--paren-comments", "--strict-chars"]] {
+        let output = run("strict_chars_comments", "+++.", args, b"");
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(stderr(&output), "--strict-chars does not allow --comment-delim or --paren-comments\n");
+    }
+}
@@ -0,0 +1,59 @@
+//! Runs every program in `tests/crashers`, inputs that once made bfi_rs panic, and checks that
+//! each now either runs or stops with a proper error
+//!
+//! A crasher is a `name.bf` program, with the input it needs in `name.in` when it reads any.
+//! Programs that still run after `STEP_LIMIT` steps are stopped there, so endless loops pass.
+
+use std::path::{Path, PathBuf};
+
+use bfi_rs::{flatten, lex, parse, run_bytes, Io, LexOptions, Machine};
+
+const STEP_LIMIT: u64 = 1_000_000;
+
+fn crashers() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/crashers");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(directory)
+        .expect("Failed to read crashers")
+        .map(|entry| entry.expect("Failed to read crashers").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bf"))
+        .collect();
+
+    programs.sort();
+    programs
+}
+
+/// Takes a crasher through lexing, parsing and running, checking that `run_bytes` agrees with
+/// what happened
+fn check(path: &Path) {
+    let source = std::fs::read(path).expect("Failed to read crasher");
+    let input = std::fs::read(path.with_extension("in")).unwrap_or_default();
+
+    let program = match parse(lex(String::from_utf8_lossy(&source).into_owned(), LexOptions::default())) {
+        Ok(program) => program,
+        Err(_) => {
+            assert!(run_bytes(&source, &input).is_err(), "{} parsed in run_bytes", path.display());
+            return;
+        },
+    };
+
+    let mut machine = Machine::new(1024);
+    let mut io = Io::new(&input[..], std::io::sink());
+
+    // run_bytes has no limit, so only programs that end within this one go through it
+    match machine.run_steps(&flatten(&program), STEP_LIMIT, &mut io) {
+        Ok(true) => (),
+        Ok(false) => assert!(run_bytes(&source, &input).is_ok(), "{} failed in run_bytes", path.display()),
+        Err(_) => assert!(run_bytes(&source, &input).is_err(), "{} ran in run_bytes", path.display()),
+    }
+}
+
+#[test]
+fn crashers_stop_without_panicking() {
+    let programs = crashers();
+    assert!(!programs.is_empty());
+
+    for program in programs {
+        let outcome = std::panic::catch_unwind(|| check(&program));
+        assert!(outcome.is_ok(), "{} panicked", program.display());
+    }
+}
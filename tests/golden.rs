@@ -0,0 +1,67 @@
+//! Runs every program in `tests/fixtures` and compares what it writes with its golden output
+//!
+//! A fixture is a `name.bf` program with the expected output in `name.out`, and the input in
+//! `name.in` when it reads any. Run with `BFI_RS_REGENERATE=1` to write the golden outputs from
+//! what the programs write now, after checking that to be right.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixtures() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(directory)
+        .expect("Failed to read fixtures")
+        .map(|entry| entry.expect("Failed to read fixtures").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bf"))
+        .collect();
+
+    programs.sort();
+    programs
+}
+
+/// Runs a fixture with its input and the given options, returning what it wrote
+fn run_fixture_with(program: &Path, options: &[&str]) -> Vec<u8> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bfi_rs"));
+
+    let input = program.with_extension("in");
+    if input.exists() {
+        command.arg("--input").arg(&input);
+    }
+
+    let output = command.args(options).arg(program).output().expect("Failed to start bfi_rs");
+    assert!(output.status.success(), "{} failed: {}", program.display(), String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+fn run_fixture(program: &Path) -> Vec<u8> {
+    run_fixture_with(program, &[])
+}
+
+#[test]
+fn fixtures_write_their_golden_output() {
+    let regenerate = std::env::var_os("BFI_RS_REGENERATE").is_some();
+    let programs = fixtures();
+    assert!(!programs.is_empty());
+
+    for program in programs {
+        let written = run_fixture(&program);
+        let golden = program.with_extension("out");
+
+        if regenerate {
+            std::fs::write(&golden, &written).expect("Failed to write golden output");
+            continue;
+        }
+
+        let expected = std::fs::read(&golden).unwrap_or_else(|_| panic!("{} has no golden output", program.display()));
+        assert_eq!(written.escape_ascii().to_string(), expected.escape_ascii().to_string(), "{}", program.display());
+    }
+}
+
+#[test]
+fn every_pass_keeps_the_fixture_outputs() {
+    for program in fixtures() {
+        // --verify-opts fails when any pass changes what the program writes
+        let report = String::from_utf8(run_fixture_with(&program, &["--verify-opts"])).unwrap();
+        assert!(report.contains("fold output: same output"), "{}: {}", program.display(), report);
+    }
+}